@@ -5,6 +5,8 @@ import os
 import json
 
 API_ENDPOINT = 'http://localhost:9000/lambda-url/request-handler'
+# Scheduled functions are invoked through `cargo lambda watch`'s Lambda API
+FUNCTIONS_ENDPOINT = 'http://localhost:9000/2015-03-31/functions'
 DATABASE_ENDPOINT = 'http://localhost:8000'
 SETTINGS_KEY = {'PK': {'S': 'settings'}, 'SK': {'S': 'settings'}}
 TABLE_NAME = os.environ['TABLE_NAME']
@@ -47,6 +49,24 @@ def set_is_banned(user_id, value, dynamodb):
     )
 
 
+def set_is_shadow_banned(user_id, value, dynamodb):
+    dynamodb.update_item(
+        TableName=TABLE_NAME,
+        Key={
+            'PK': {
+                'S': f'user#{user_id}'
+            },
+            'SK': {
+                'S': f'user#{user_id}'
+            }
+        },
+        UpdateExpression='SET is_shadow_banned = :val',
+        ExpressionAttributeValues={':val': {
+            'BOOL': value
+        }},
+    )
+
+
 def update_voting_is_disabled(value, dynamodb):
     dynamodb.update_item(
         TableName=TABLE_NAME,
@@ -66,13 +86,110 @@ def get_scores(hostnames):
     return [x['score'] for x in response.json()]
 
 
-def vote(hostname, value, user_id):
+def invoke(function, payload={}):
+    response = requests.post(f'{FUNCTIONS_ENDPOINT}/{function}/invocations',
+                             json=payload)
+    assert response.status_code == 200
+    return response.json()
+
+
+def get_filter_list(format):
+    response = requests.get(f'{API_ENDPOINT}/v1/filter-list',
+                            params={'format': format})
+    assert response.status_code == 200
+    return response.text.splitlines()
+
+
+def post_scores(hostnames):
+    links = [{'hostname': hostname} for hostname in hostnames]
+    response = requests.post(f'{API_ENDPOINT}/v1/scores',
+                             json={'links': links})
+    assert response.status_code == 200
+    return [x['score'] for x in response.json()]
+
+
+def get_reasons(hostnames):
+    links = [{'hostname': hostname} for hostname in hostnames]
+    params = {'from': json.dumps({'links': links})}
+    response = requests.get(f'{API_ENDPOINT}/v1/scores', params=params)
+    assert response.status_code == 200
+    return [x.get('reasons', {}) for x in response.json()]
+
+
+def get_moderated(hostnames):
+    links = [{'hostname': hostname} for hostname in hostnames]
+    params = {'from': json.dumps({'links': links})}
+    response = requests.get(f'{API_ENDPOINT}/v1/scores', params=params)
+    assert response.status_code == 200
+    return [(x['score'], x.get('moderated', False)) for x in response.json()]
+
+
+def put_link_override(hostname, score, expires_at, dynamodb):
+    item = {
+        'PK': {
+            'S': f'link#{hostname}'
+        },
+        'SK': {
+            'S': 'override'
+        },
+        'entity_type': {
+            'S': 'LinkOverride'
+        },
+        'score': {
+            'S': score
+        },
+        'reason': {
+            'S': 'Integration test'
+        },
+    }
+    if expires_at is not None:
+        item['expires_at'] = {'S': expires_at}
+    dynamodb.put_item(TableName=TABLE_NAME, Item=item)
+
+
+def vote(hostname, value, user_id, reason=None):
     vote = {"link": {"hostname": hostname}, "value": value, "user_id": user_id}
+    if reason is not None:
+        vote['reason'] = reason
     response = requests.post(f'{API_ENDPOINT}/v1/vote', json=vote)
     assert response.status_code == 200
     return
 
 
+def queued_votes(votes):
+    votes = [{
+        "link": {
+            "hostname": hostname
+        },
+        "value": value,
+        "user_id": user_id,
+        "created_at": created_at
+    } for (hostname, value, user_id, created_at) in votes]
+    response = requests.post(f'{API_ENDPOINT}/v1/votes',
+                             json={'votes': votes})
+    assert response.status_code == 200
+    return response.json()
+
+
+def set_clock(now):
+    response = requests.post(f'{API_ENDPOINT}/v1/clock', json={'now': now})
+    assert response.status_code == 200
+    return
+
+
+def get_link_history(day, hostname, dynamodb):
+    item = dynamodb.get_item(TableName=TABLE_NAME,
+                             Key={
+                                 'PK': {
+                                     'S': f'day#{day}'
+                                 },
+                                 'SK': {
+                                     'S': f'link#{hostname}'
+                                 }
+                             })['Item']
+    return (int(item['count_of_votes']['N']), int(item['sum_of_votes']['N']))
+
+
 def assert_vote_fails(hostname, value, user_id, reason):
     vote = {"link": {"hostname": hostname}, "value": value, "user_id": user_id}
     response = requests.post(f'{API_ENDPOINT}/v1/vote', json=vote)
@@ -82,8 +199,9 @@ def assert_vote_fails(hostname, value, user_id, reason):
 
 
 def test_backend(dynamodb):
-    # Make sure we're using a stubbed out time
-    assert os.environ['USE_SYSTEM_TIME'] == 'false'
+    # Make sure we're using a fake clock, starting from where it was set
+    assert os.environ['FAKE_TIME'] == '2022-07-27T12:30:00Z'
+    set_clock(os.environ['FAKE_TIME'])
 
     # Simple test to make sure we can get a score
     assert get_scores(['a.com', 'b.com']) == ['NoScore', 'NoScore']
@@ -96,6 +214,7 @@ def test_backend(dynamodb):
         vote('good.com', 1, f"beda{i:04}-1822-4342-0990-b92d94d9489a")
     assert get_scores(['good.com']) == ['Good']
     assert get_scores(['good.com']) == ['Good']
+    assert post_scores(['good.com', 'a.com']) == ['Good', 'NoScore']
 
     # Check that the Bad scoring works
     for i in range(9):
@@ -105,6 +224,9 @@ def test_backend(dynamodb):
         vote('bad.com', -1, f"beda{i:04}-1822-4342-0990-b92d94d9489a")
     assert get_scores(['bad.com']) == ['Bad']
     assert get_scores(['bad.com']) == ['Bad']
+    assert invoke('compute-filter-lists')['count_of_links'] >= 1
+    assert '||bad.com^' in get_filter_list('adblock')
+    assert '0.0.0.0 good.com' not in get_filter_list('hosts')
 
     # Check that the Controversial scoring works
     for i in range(30):
@@ -168,4 +290,224 @@ def test_backend(dynamodb):
     vote('bad.com', -1, user)  # all good again
     vote('other.com', -1, user)  # all good again
 
+    # Check that shadow banned users can vote, but it doesn't count
+    user = "beda0000-9822-4342-0990-b92d94d9489a"
+    vote('shadowed.com', 1, user)
+    set_is_shadow_banned(user, True, dynamodb)
+    for i in range(9):
+        vote('shadowed.com', -1, user)
+        vote(f'shadowed{i}.com', -1, user)
+    assert get_scores(['shadowed.com']) == ['NoScore']
+    assert get_scores(['shadowed8.com']) == ['NoScore']
+    # The daily limit still applies, the same as for everyone else
+    assert_vote_fails('shadowed9.com', -1, user,
+                      'User has voted too many times today')
+    set_is_shadow_banned(user, False, dynamodb)
+
+    # Check that lifting a shadow ban applies every stored vote, even past the daily limit
+    user = "beda0000-a822-4342-0990-b92d94d9489a"
+    vote('lifted0.com', 1, user)
+    set_is_shadow_banned(user, True, dynamodb)
+    for i in range(1, 10):
+        vote(f'lifted{i}.com', 1, user)
+    set_clock('2022-07-28T12:30:00Z')
+    for i in range(10, 13):
+        vote(f'lifted{i}.com', 1, user)
+    set_clock(os.environ['FAKE_TIME'])
+    assert invoke('lift-shadow-ban', {'user_id': user}) == {
+        'count_of_applied_votes': 12
+    }
+    assert get_link_history('2022-07-27', 'lifted9.com', dynamodb) == (1, 1)
+    assert get_link_history('2022-07-28', 'lifted12.com', dynamodb) == (1, 1)
+
+    # Check that downvote reasons are counted per link
+    user = "beda0000-7822-4342-0990-b92d94d9489a"
+    other_user = "beda0001-7822-4342-0990-b92d94d9489a"
+    vote('spammy.com', -1, user, 'SeoSpam')
+    vote('spammy.com', -1, other_user, 'AiGenerated')
+    assert get_reasons(['spammy.com']) == [{'SeoSpam': 1, 'AiGenerated': 1}]
+    vote('spammy.com', -1, other_user, 'SeoSpam')  # Changed their mind
+    assert get_reasons(['spammy.com']) == [{'SeoSpam': 2}]
+    vote('spammy.com', 1, user)
+    assert get_reasons(['spammy.com']) == [{'SeoSpam': 1}]
+
+    # Check that overrides replace the score until they expire
+    put_link_override('spammy.com', 'Good', None, dynamodb)
+    put_link_override('overridden.com', 'Bad', '2030-01-01T00:00:00Z',
+                      dynamodb)
+    put_link_override('expired.com', 'Bad', '2020-01-01T00:00:00Z', dynamodb)
+    assert get_moderated(['spammy.com', 'overridden.com', 'expired.com'
+                          ]) == [('Good', True), ('Bad', True),
+                                 ('NoScore', False)]
+
+    # Check that queued votes are applied with partial failures reported
+    user = "beda0000-6822-4342-0990-b92d94d9489a"
+    results = queued_votes([
+        ('queued.com', -1, user, '2022-07-27T11:00:00Z'),
+        ('queued.com', 1, user, '2022-07-26T11:00:00Z'),
+        ('queued.com', 1, user, '2022-07-28T11:00:00Z'),  # In the future
+        ('old-queued.com', 1, user, '2022-01-01T11:00:00Z'),  # Too old
+    ])
+    assert results == [
+        {'success': True},
+        {'success': True},
+        {'success': False, 'error': 'Vote timestamp is out of range'},
+        {'success': False, 'error': 'Vote timestamp is out of range'},
+    ]
+    # The later vote wins, so an older queued vote can't overwrite it
+    results = queued_votes([('queued.com', 1, user, '2022-07-26T12:00:00Z')])
+    assert results == [{
+        'success': False,
+        'error': 'A newer vote already exists'
+    }]
+
+    # Check that changing a vote on another day moves it out of the old day
+    user = "beda0000-4822-4342-0990-b92d94d9489a"
+    vote('multiday.com', 1, user)
+    set_clock('2022-07-28T12:30:00Z')
+    vote('multiday.com', -1, user)
+    assert get_link_history('2022-07-27', 'multiday.com', dynamodb) == (0, 0)
+    assert get_link_history('2022-07-28', 'multiday.com',
+                            dynamodb) == (1, -1)
+
+    # Check that the daily vote limit resets the next day
+    user = "beda0000-3822-4342-0990-b92d94d9489a"
+    for i in range(10):
+        vote(f"daily{i}.com", 1, user)
+    assert_vote_fails("daily10.com", 1, user,
+                      "User has voted too many times today")
+    set_clock('2022-07-29T12:30:00Z')
+    vote("daily10.com", 1, user)
+    set_clock(os.environ['FAKE_TIME'])
+
+    # Check that queued votes count towards the day they arrive, not the day they were cast
+    user = "beda0000-2822-4342-0990-b92d94d9489a"
+    results = queued_votes([(f"spread{i}.com", 1, user,
+                             f"2022-07-{24 + i % 3}T12:00:00Z")
+                            for i in range(12)])
+    assert results[-2:] == [{
+        'success': False,
+        'error': 'User has voted too many times today'
+    }] * 2
+
+    # Check that a single link can be looked up, and the wrong method is refused
+    response = requests.get(f'{API_ENDPOINT}/v1/links/good.com')
+    assert response.status_code == 200
+    assert response.json()['score'] == get_scores(['good.com'])[0]
+    response = requests.get(f'{API_ENDPOINT}/v1/vote')
+    assert response.status_code == 405
+    assert response.headers['Allow'] == 'POST'
+
+    # Check that the OpenAPI document is served
+    response = requests.get(f'{API_ENDPOINT}/v1/openapi.json')
+    assert response.status_code == 200
+    assert '/v1/vote' in response.json()['paths']
+
+    # Check that the admin routes are off without an ADMIN_KEY
+    response = requests.post(f'{API_ENDPOINT}/v1/admin/filter-list')
+    assert response.status_code == 404
+
+    # Check that resolving a review unfreezes the link's score
+    user = "f0e10000-2822-4342-0990-b92d94d9489a"
+    vote("frozen.com", 1, user)
+    before = get_moderated(['frozen.com'])
+    dynamodb.put_item(TableName=TABLE_NAME,
+                      Item={
+                          'PK': {
+                              'S': 'review'
+                          },
+                          'SK': {
+                              'S': 'link#frozen.com#2022-07-27'
+                          },
+                          'entity_type': {
+                              'S': 'LinkReview'
+                          },
+                          'link': {
+                              'S': 'frozen.com'
+                          },
+                          'day': {
+                              'S': '2022-07-27'
+                          },
+                          'is_frozen': {
+                              'BOOL': True
+                          },
+                      })
+    dynamodb.update_item(
+        TableName=TABLE_NAME,
+        Key={
+            'PK': {
+                'S': 'link#frozen.com'
+            },
+            'SK': {
+                'S': 'link#frozen.com'
+            }
+        },
+        UpdateExpression='SET frozen_score = :score',
+        ExpressionAttributeValues={':score': {
+            'S': 'Bad'
+        }},
+    )
+    assert get_moderated(['frozen.com']) == [('Bad', True)]
+    assert invoke('resolve-link-review', {
+        'link': {
+            'hostname': 'frozen.com'
+        },
+        'day': '2022-07-27'
+    }) == {
+        'is_unfrozen': True
+    }
+    assert get_moderated(['frozen.com']) == before
+    assert 'Item' not in dynamodb.get_item(
+        TableName=TABLE_NAME,
+        Key={
+            'PK': {
+                'S': 'review'
+            },
+            'SK': {
+                'S': 'link#frozen.com#2022-07-27'
+            }
+        })
+
+    # Check that the jobs compute the stats from the votes
+    def get_stats():
+        invoke('compute-stats')
+        invoke('compute-filter-lists')
+        response = requests.get(f'{API_ENDPOINT}/v1/stats')
+        assert response.status_code == 200
+        return response.json()
+
+    before = get_stats()
+    user = "57a70000-2822-4342-0990-b92d94d9489a"
+    vote("stats-one.com", 1, user)
+    vote("stats-two.com", -1, user)
+    after = get_stats()
+    assert after['count_of_votes_today'] == before['count_of_votes_today'] + 2
+    assert after['count_of_votes_this_week'] == before[
+        'count_of_votes_this_week'] + 2
+    assert after['days'][-1] == {
+        'day': '2022-07-27',
+        'count_of_votes': before['days'][-1]['count_of_votes'] + 2,
+        'count_of_active_voters':
+        before['days'][-1]['count_of_active_voters'] + 1
+    }
+    assert after['count_of_rated_links'] == before['count_of_rated_links'] + 2
+    assert after['updated_at'] == os.environ['FAKE_TIME']
+
+    # Check that the health checks pass against the local table
+    response = requests.get(f'{API_ENDPOINT}/v1/health')
+    assert response.status_code == 200
+    assert response.json()['config']['table_name'] == TABLE_NAME
+    response = requests.get(f'{API_ENDPOINT}/v1/health/ready')
+    assert response.status_code == 200
+    assert response.json()['ready']
+
+    # Check that CORS preflight requests are answered
+    response = requests.options(f'{API_ENDPOINT}/v1/vote',
+                                headers={
+                                    'Origin': 'moz-extension://abc',
+                                    'Access-Control-Request-Method': 'POST'
+                                })
+    assert response.status_code == 204
+    assert 'POST' in response.headers['Access-Control-Allow-Methods']
+
     # TODO: test incorrectly formatted requests
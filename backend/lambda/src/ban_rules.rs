@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+
+use crate::{
+    dynamodb::*,
+    reputation::consensus,
+    types::{database::LinkDetail, Config, Link},
+};
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use lambda_http::Error;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BanAction {
+    Ban,
+    ShadowBan,
+}
+
+/// Rules are stored as JSON in the settings, for example
+/// `{"name": "Vote flood", "rule": {"type": "VoteRate", "maximum_votes": 30, "minutes": 10}, "action": "ShadowBan"}`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BanRuleConfig {
+    pub name: String,
+    pub rule: BanRule,
+    pub action: BanAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum BanRule {
+    /// More than `maximum_votes` votes within any `minutes` long window
+    VoteRate { maximum_votes: u32, minutes: i64 },
+    /// At least `minimum_votes` votes, every one of them within `hours` of the link's first vote
+    NewLinksOnly { minimum_votes: u32, hours: i64 },
+    /// At least `minimum_disagreement` of the votes on decided links go against the consensus
+    Disagreement {
+        minimum_decided_votes: u32,
+        minimum_disagreement: f64,
+    },
+}
+impl BanRule {
+    /// Rules that only need the user's recent votes are cheap enough to check on
+    /// every vote, the rest are left to the scheduled job
+    pub fn is_checked_per_vote(&self) -> bool {
+        matches!(self, BanRule::VoteRate { .. })
+    }
+
+    // Why the votes break the rule, if they do. Votes are sorted oldest first,
+    // and the rate is checked on when the votes were received since queued votes
+    // carry whatever time the client gives them.
+    fn evaluate(&self, votes: &[VoteActivity], received_at: &[DateTime<Utc>]) -> Option<String> {
+        match *self {
+            BanRule::VoteRate {
+                maximum_votes,
+                minutes,
+            } => {
+                let window = Duration::minutes(minutes);
+                let mut start = 0;
+                for end in 0..received_at.len() {
+                    while received_at[end] - received_at[start] >= window {
+                        start += 1;
+                    }
+                    let count_of_votes = (end - start + 1) as u32;
+                    if count_of_votes > maximum_votes {
+                        return Some(format!(
+                            "{} votes within {} minutes",
+                            count_of_votes, minutes
+                        ));
+                    }
+                }
+                None
+            }
+            BanRule::NewLinksOnly {
+                minimum_votes,
+                hours,
+            } => {
+                let is_new_link = |vote: &VoteActivity| {
+                    vote.link_created_at.is_some_and(|link_created_at| {
+                        vote.created_at - link_created_at < Duration::hours(hours)
+                    })
+                };
+                if votes.len() as u32 >= minimum_votes && votes.iter().all(is_new_link) {
+                    Some(format!(
+                        "All {} votes were on links less than {} hours old",
+                        votes.len(),
+                        hours
+                    ))
+                } else {
+                    None
+                }
+            }
+            BanRule::Disagreement {
+                minimum_decided_votes,
+                minimum_disagreement,
+            } => {
+                let decided_votes: Vec<bool> = votes
+                    .iter()
+                    .filter_map(|vote| vote.consensus.map(|consensus| consensus == vote.value))
+                    .collect();
+                let count_of_decided_votes = decided_votes.len() as u32;
+                let count_of_disagreeing_votes =
+                    decided_votes.iter().filter(|agrees| !**agrees).count() as u32;
+                if count_of_decided_votes < minimum_decided_votes {
+                    return None;
+                }
+                let disagreement =
+                    count_of_disagreeing_votes as f64 / count_of_decided_votes as f64;
+                if disagreement >= minimum_disagreement {
+                    Some(format!(
+                        "Disagreed with the consensus on {} of {} links",
+                        count_of_disagreeing_votes, count_of_decided_votes
+                    ))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// A user's vote along with what's known about the link it was on
+#[derive(Debug, Clone)]
+pub struct VoteActivity {
+    pub link: Link,
+    pub value: i32,
+    pub created_at: DateTime<Utc>,
+    pub link_created_at: Option<DateTime<Utc>>,
+    pub consensus: Option<i32>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BanDecision {
+    pub rule: String,
+    pub action: BanAction,
+    pub reason: String,
+}
+
+/// The first of the rules that the votes break. Votes and the times they were
+/// received must be sorted oldest first.
+pub fn evaluate_ban_rules(
+    rules: &[BanRuleConfig],
+    votes: &[VoteActivity],
+    received_at: &[DateTime<Utc>],
+) -> Option<BanDecision> {
+    rules.iter().find_map(|rule_config| {
+        rule_config
+            .rule
+            .evaluate(votes, received_at)
+            .map(|reason| BanDecision {
+                rule: rule_config.name.clone(),
+                action: rule_config.action,
+                reason,
+            })
+    })
+}
+
+/// When the user's latest votes were received, including one received `now`.
+/// Only as many as the `VoteRate` rules need are kept, so they fit on the `User`.
+pub fn recent_votes_received_at(
+    previous: &[String],
+    now: DateTime<Utc>,
+    rules: &[BanRuleConfig],
+) -> Result<Vec<String>, Error> {
+    let (most_votes, longest_window) = rules
+        .iter()
+        .filter_map(|rule_config| match rule_config.rule {
+            BanRule::VoteRate {
+                maximum_votes,
+                minutes,
+            } => Some((maximum_votes as usize, minutes)),
+            _ => None,
+        })
+        .fold((0, 0), |(most_votes, longest_window), (votes, minutes)| {
+            (most_votes.max(votes), longest_window.max(minutes))
+        });
+    let since = now - Duration::minutes(longest_window);
+    let mut received_at = vec![];
+    for previous in previous {
+        let previous = DateTime::parse_from_rfc3339(previous)?.with_timezone(&Utc);
+        if previous > since {
+            received_at.push(previous);
+        }
+    }
+    received_at.push(now);
+    received_at.sort();
+    // One more than the highest maximum is enough to tell that it's been broken
+    let skip = received_at.len().saturating_sub(most_votes + 1);
+    Ok(received_at[skip..]
+        .iter()
+        .map(|received_at| received_at.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .collect())
+}
+
+/// Check the rules that can be checked per vote against when the user's latest
+/// votes were received, from `recent_votes_received_at`
+pub fn check_ban_rules_for_vote(
+    recent_votes_received_at: &[String],
+    rules: &[BanRuleConfig],
+) -> Result<Option<BanDecision>, Error> {
+    let rules: Vec<BanRuleConfig> = rules
+        .iter()
+        .filter(|rule_config| rule_config.rule.is_checked_per_vote())
+        .cloned()
+        .collect();
+    let received_at = parse_received_at(recent_votes_received_at)?;
+    Ok(evaluate_ban_rules(&rules, &[], &received_at))
+}
+
+fn parse_received_at(received_at: &[String]) -> Result<Vec<DateTime<Utc>>, Error> {
+    received_at
+        .iter()
+        .map(|received_at| Ok(DateTime::parse_from_rfc3339(received_at)?.with_timezone(&Utc)))
+        .collect()
+}
+
+/// Check every user that isn't already banned against all of the rules. In a dry
+/// run nothing is written, which makes it safe to try out new rules against the
+/// votes that already exist.
+pub async fn apply_ban_rules(
+    rules: &[BanRuleConfig],
+    dry_run: bool,
+    config: &Config,
+    dynamo_db_client: &Client,
+    now: DateTime<Utc>,
+) -> Result<Vec<(Uuid, BanDecision)>, Error> {
+    let mut links: HashMap<Link, (Option<DateTime<Utc>>, Option<i32>)> = HashMap::new();
+    let mut decisions = vec![];
+    let banned_at = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    for (user_id, user) in scan_users(config, dynamo_db_client).await? {
+        if user.is_banned || user.is_shadow_banned {
+            continue;
+        }
+        let user_votes = query_user_votes(&user_id, config, dynamo_db_client).await?;
+
+        let unknown_links: Vec<HashMap<String, AttributeValue>> = user_votes
+            .iter()
+            .filter(|user_vote| !links.contains_key(&user_vote.link))
+            .map(|user_vote| get_link_detail(&user_vote.link))
+            .collect();
+        for item in batch_get_items(unknown_links, config, dynamo_db_client).await? {
+            let link_detail = LinkDetail::try_from(&item)?;
+            let link_created_at = match &link_detail.created_at {
+                Some(created_at) => {
+                    Some(DateTime::parse_from_rfc3339(created_at)?.with_timezone(&Utc))
+                }
+                None => None,
+            };
+            links.insert(
+                link_detail.link.clone(),
+                (link_created_at, consensus(&link_detail, config)),
+            );
+        }
+
+        let mut votes = vec![];
+        for user_vote in user_votes {
+            let (link_created_at, consensus) =
+                links.get(&user_vote.link).copied().unwrap_or_default();
+            votes.push(VoteActivity {
+                created_at: DateTime::parse_from_rfc3339(&user_vote.created_at)?
+                    .with_timezone(&Utc),
+                link: user_vote.link,
+                value: user_vote.value,
+                link_created_at,
+                consensus,
+            });
+        }
+        votes.sort_by_key(|vote| vote.created_at);
+        let received_at = parse_received_at(&user.recent_votes_received_at)?;
+
+        if let Some(decision) = evaluate_ban_rules(rules, &votes, &received_at) {
+            info!(
+                "Ban rule matched [user_id={}, decision={:?}, dry_run={}]",
+                user_id, decision, dry_run
+            );
+            if !dry_run {
+                dynamo_db_client
+                    .transact_write_items()
+                    .transact_items(ban_user(&user_id, &decision, &banned_at, config))
+                    .send()
+                    .await?;
+            }
+            decisions.push((user_id, decision));
+        }
+    }
+
+    Ok(decisions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote_activity(
+        minutes: i64,
+        link_age_in_hours: Option<i64>,
+        agrees: Option<bool>,
+    ) -> VoteActivity {
+        let created_at = DateTime::parse_from_rfc3339("2023-02-08T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+            + Duration::minutes(minutes);
+        VoteActivity {
+            link: Link::new(&format!("link{}.com", minutes)),
+            value: 1,
+            created_at,
+            link_created_at: link_age_in_hours.map(|hours| created_at - Duration::hours(hours)),
+            consensus: agrees.map(|agrees| if agrees { 1 } else { -1 }),
+        }
+    }
+
+    fn rule(name: &str, rule: BanRule, action: BanAction) -> BanRuleConfig {
+        BanRuleConfig {
+            name: name.to_string(),
+            rule,
+            action,
+        }
+    }
+
+    #[test]
+    fn test_ban_rule_config_format() {
+        let rules: Vec<BanRuleConfig> = serde_json::from_str(
+            r#"[{"name": "Vote flood", "rule": {"type": "VoteRate", "maximum_votes": 30, "minutes": 10}, "action": "ShadowBan"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            vec![rule(
+                "Vote flood",
+                BanRule::VoteRate {
+                    maximum_votes: 30,
+                    minutes: 10
+                },
+                BanAction::ShadowBan
+            )]
+        );
+    }
+
+    #[test]
+    fn test_evaluate_ban_rules() {
+        let vote_rate = rule(
+            "Vote flood",
+            BanRule::VoteRate {
+                maximum_votes: 3,
+                minutes: 10,
+            },
+            BanAction::ShadowBan,
+        );
+        let new_links_only = rule(
+            "New links only",
+            BanRule::NewLinksOnly {
+                minimum_votes: 3,
+                hours: 24,
+            },
+            BanAction::Ban,
+        );
+        let disagreement = rule(
+            "Contrarian",
+            BanRule::Disagreement {
+                minimum_decided_votes: 4,
+                minimum_disagreement: 0.75,
+            },
+            BanAction::Ban,
+        );
+        let rules = vec![vote_rate, new_links_only, disagreement];
+
+        // Spread out votes on old links, mostly agreeing
+        let votes: Vec<VoteActivity> = (0..5)
+            .map(|i| vote_activity(i * 10, Some(100), Some(i != 0)))
+            .collect();
+        assert_eq!(evaluate_ban_rules(&rules, &votes, &[]), None);
+
+        // 4 votes received within 10 minutes
+        let received_at: Vec<DateTime<Utc>> = [0, 30, 31, 35, 39]
+            .iter()
+            .map(|minutes| vote_activity(*minutes, None, None).created_at)
+            .collect();
+        assert_eq!(
+            evaluate_ban_rules(&rules, &votes, &received_at),
+            Some(BanDecision {
+                rule: "Vote flood".to_string(),
+                action: BanAction::ShadowBan,
+                reason: "4 votes within 10 minutes".to_string(),
+            })
+        );
+
+        // Only ever on new links, links without a known age count as old
+        let mut votes: Vec<VoteActivity> = (0..3)
+            .map(|i| vote_activity(i * 60, Some(2), None))
+            .collect();
+        assert_eq!(
+            evaluate_ban_rules(&rules, &votes, &[]).unwrap().reason,
+            "All 3 votes were on links less than 24 hours old"
+        );
+        votes.push(vote_activity(500, None, None));
+        assert_eq!(evaluate_ban_rules(&rules, &votes, &[]), None);
+
+        // Mostly disagreeing, once there are enough decided votes
+        let mut votes: Vec<VoteActivity> = (0..3)
+            .map(|i| vote_activity(i * 60, Some(100), Some(false)))
+            .collect();
+        assert_eq!(evaluate_ban_rules(&rules, &votes, &[]), None);
+        votes.push(vote_activity(500, Some(100), Some(true)));
+        assert_eq!(
+            evaluate_ban_rules(&rules, &votes, &[]).unwrap().reason,
+            "Disagreed with the consensus on 3 of 4 links"
+        );
+    }
+
+    #[test]
+    fn test_vote_rate_uses_received_at() {
+        let rules = vec![rule(
+            "Vote flood",
+            BanRule::VoteRate {
+                maximum_votes: 3,
+                minutes: 10,
+            },
+            BanAction::ShadowBan,
+        )];
+        let now = vote_activity(0, None, None).created_at;
+
+        // Queued votes that claim to be a day apart still arrive together
+        let mut received_at = vec![];
+        for i in 0..3 {
+            received_at =
+                recent_votes_received_at(&received_at, now + Duration::seconds(i), &rules).unwrap();
+            assert_eq!(
+                check_ban_rules_for_vote(&received_at, &rules).unwrap(),
+                None
+            );
+        }
+        let flooded =
+            recent_votes_received_at(&received_at, now + Duration::seconds(3), &rules).unwrap();
+        assert_eq!(
+            check_ban_rules_for_vote(&flooded, &rules)
+                .unwrap()
+                .unwrap()
+                .reason,
+            "4 votes within 10 minutes"
+        );
+
+        // Only the votes within the window are kept, up to one more than the maximum
+        assert_eq!(flooded.len(), 4);
+        let later =
+            recent_votes_received_at(&flooded, now + Duration::minutes(20), &rules).unwrap();
+        assert_eq!(later, vec!["2023-02-08T12:20:00Z"]);
+        assert_eq!(
+            recent_votes_received_at(&flooded, now, &[]).unwrap().len(),
+            1
+        );
+    }
+}
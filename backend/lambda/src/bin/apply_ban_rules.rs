@@ -0,0 +1,62 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use request_handler::{
+    ban_rules::{apply_ban_rules, BanRuleConfig},
+    clock::clock_from_config,
+    dynamodb::get_settings,
+    setup,
+    types::database::Settings,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::*;
+
+/// Scheduled runs send an empty-ish event and use the rules from the settings.
+/// Invoke by hand with `{"dry_run": true, "rules": [...]}` to try out new rules.
+#[derive(Deserialize)]
+struct ApplyBanRulesEvent {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    rules: Option<Vec<BanRuleConfig>>,
+}
+
+// Runs on a schedule, see `ApplyBanRules` in the SAM templates
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let (config, dynamo_db_client) = setup().await;
+    let clock = clock_from_config(&config);
+    info!("Loaded config [{:?}, clock={:?}]", config, clock);
+
+    let (clock, config, dynamo_db_client) = (clock.as_ref(), &config, &dynamo_db_client);
+    run(service_fn(
+        |event: LambdaEvent<ApplyBanRulesEvent>| async move {
+            let ApplyBanRulesEvent { dry_run, rules } = event.payload;
+            let rules = match rules {
+                Some(rules) => rules,
+                None => {
+                    let settings = dynamo_db_client
+                        .get_item()
+                        .table_name(&config.table_name)
+                        .set_key(Some(get_settings()))
+                        .send()
+                        .await?;
+                    Settings::try_from(settings.item().ok_or("No settings")?)?.ban_rules
+                }
+            };
+
+            let decisions =
+                apply_ban_rules(&rules, dry_run, config, dynamo_db_client, clock.now()).await?;
+            info!(
+                "Applied ban rules [count={}, dry_run={}]",
+                decisions.len(),
+                dry_run
+            );
+            let bans: Vec<Value> = decisions
+                .iter()
+                .map(|(user_id, decision)| json!({ "user_id": user_id, "decision": decision }))
+                .collect();
+            Ok::<Value, Error>(json!({ "dry_run": dry_run, "bans": bans }))
+        },
+    ))
+    .await
+}
@@ -0,0 +1,43 @@
+use clap::Parser;
+use lambda_http::Error;
+use request_handler::{
+    consistency::{check_consistency, repair_discrepancies},
+    setup,
+};
+use tracing::*;
+
+/// Recompute the vote aggregates from the votes themselves and report any that
+/// have drifted, optionally repairing them
+#[derive(Parser)]
+struct Arguments {
+    /// Overwrite the drifted aggregates with their recomputed totals
+    #[arg(long)]
+    repair: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let arguments = Arguments::parse();
+    let (config, dynamo_db_client) = setup().await;
+
+    let discrepancies = check_consistency(&config, &dynamo_db_client).await?;
+    for discrepancy in &discrepancies {
+        println!(
+            "{:?}: expected {:?}, stored {:?}",
+            discrepancy.aggregate, discrepancy.expected, discrepancy.stored
+        );
+    }
+    info!(
+        "Checked consistency [count_of_discrepancies={}]",
+        discrepancies.len()
+    );
+
+    if arguments.repair && !discrepancies.is_empty() {
+        let summary = repair_discrepancies(&discrepancies, &config, &dynamo_db_client).await?;
+        info!(
+            "Repaired aggregates [count_of_repaired_items={}, count_of_skipped_items={}]",
+            summary.count_of_repaired_items, summary.count_of_skipped_items
+        );
+    }
+    Ok(())
+}
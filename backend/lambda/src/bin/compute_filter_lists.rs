@@ -0,0 +1,19 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use request_handler::{clock::clock_from_config, filter_lists::update_filter_list, setup};
+use serde_json::{json, Value};
+use tracing::*;
+
+// Runs on a schedule, see `ComputeFilterLists` in the SAM templates
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let (config, dynamo_db_client) = setup().await;
+    let clock = clock_from_config(&config);
+    info!("Loaded config [{:?}, clock={:?}]", config, clock);
+
+    run(service_fn(|_event: LambdaEvent<Value>| async {
+        let links = update_filter_list(clock.now(), &config, &dynamo_db_client).await?;
+        info!("Computed filter list [count_of_links={}]", links.len());
+        Ok::<Value, Error>(json!({ "count_of_links": links.len() }))
+    }))
+    .await
+}
@@ -0,0 +1,23 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use request_handler::{clock::clock_from_config, setup, stats::update_daily_stats};
+use serde_json::{json, Value};
+use tracing::*;
+
+// Runs on a schedule, see `ComputeStats` in the SAM templates
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let (config, dynamo_db_client) = setup().await;
+    let clock = clock_from_config(&config);
+    info!("Loaded config [{:?}, clock={:?}]", config, clock);
+
+    run(service_fn(|_event: LambdaEvent<Value>| async {
+        let all_daily_stats = update_daily_stats(clock.now(), &config, &dynamo_db_client).await?;
+        let today = all_daily_stats.last().ok_or("No daily stats")?;
+        info!("Computed daily stats [today={:?}]", today);
+        Ok::<Value, Error>(json!({
+            "count_of_votes_today": today.count_of_votes,
+            "count_of_active_voters_today": today.count_of_active_voters,
+        }))
+    }))
+    .await
+}
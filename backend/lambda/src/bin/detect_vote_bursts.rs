@@ -0,0 +1,28 @@
+use chrono::Duration;
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use request_handler::{clock::clock_from_config, setup, vote_bursts::detect_vote_bursts};
+use serde_json::{json, Value};
+use tracing::*;
+
+// Runs on a schedule, see `DetectVoteBursts` in the SAM templates
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let (config, dynamo_db_client) = setup().await;
+    let clock = clock_from_config(&config);
+    info!("Loaded config [{:?}, clock={:?}]", config, clock);
+
+    run(service_fn(|_event: LambdaEvent<Value>| async {
+        // Yesterday is checked too, bursts just before midnight aren't finished at the last run
+        let now = clock.now();
+        let mut count_of_vote_bursts = 0;
+        for day in [now - Duration::days(1), now] {
+            let day = day.format("%Y-%m-%d").to_string();
+            count_of_vote_bursts += detect_vote_bursts(&day, now, &config, &dynamo_db_client)
+                .await?
+                .len();
+        }
+        info!("Detected vote bursts [count={}]", count_of_vote_bursts);
+        Ok::<Value, Error>(json!({ "count_of_vote_bursts": count_of_vote_bursts }))
+    }))
+    .await
+}
@@ -0,0 +1,46 @@
+use clap::Parser;
+use lambda_http::Error;
+use request_handler::{
+    clock::clock_from_config,
+    filter_lists::{bad_links, render_filter_list, scan_link_details, FilterListFormat},
+    setup,
+};
+use std::{fs, path::PathBuf};
+use tracing::*;
+
+/// Write the links that score Bad as uBlacklist, hosts and Adblock filter lists
+#[derive(Parser)]
+struct Arguments {
+    /// Where to write `ublacklist.txt`, `hosts.txt` and `adblock.txt`
+    #[arg(long, default_value = "filter_lists")]
+    output_directory: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let arguments = Arguments::parse();
+    let (config, dynamo_db_client) = setup().await;
+
+    let now = clock_from_config(&config).now();
+    let (link_details, link_overrides) = scan_link_details(&config, &dynamo_db_client).await?;
+    let links = bad_links(
+        &link_details,
+        &link_overrides,
+        config.use_weighted_scores,
+        now,
+    );
+
+    fs::create_dir_all(&arguments.output_directory)?;
+    for format in FilterListFormat::ALL {
+        let path = arguments
+            .output_directory
+            .join(format!("{}.txt", format.as_str()));
+        fs::write(&path, render_filter_list(&links, format, now))?;
+        info!(
+            "Wrote filter list [path={}, count_of_links={}]",
+            path.display(),
+            links.len()
+        );
+    }
+    Ok(())
+}
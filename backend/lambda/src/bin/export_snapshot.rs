@@ -0,0 +1,35 @@
+use clap::Parser;
+use lambda_http::Error;
+use request_handler::{clock::clock_from_config, setup, snapshots::export_snapshot};
+use std::path::PathBuf;
+use tracing::*;
+
+/// Dump every LinkDetail, LinkHistory and anonymised Vote to newline delimited
+/// JSON, for research and backups
+#[derive(Parser)]
+struct Arguments {
+    /// Where to write the manifest and one `.ndjson` file per entity type
+    #[arg(long)]
+    output_directory: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let arguments = Arguments::parse();
+    let (config, dynamo_db_client) = setup().await;
+
+    let manifest = export_snapshot(
+        &arguments.output_directory,
+        clock_from_config(&config).now(),
+        &config,
+        &dynamo_db_client,
+    )
+    .await?;
+    for file in &manifest.files {
+        info!(
+            "Exported snapshot file [file_name={}, count_of_items={}, sha256={}]",
+            file.file_name, file.count_of_items, file.sha256
+        );
+    }
+    Ok(())
+}
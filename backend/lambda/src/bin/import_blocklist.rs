@@ -0,0 +1,105 @@
+use clap::{Parser, ValueEnum};
+use lambda_http::Error;
+use request_handler::{
+    blocklists::{parse_blocklist, seed_links, BlocklistFormat, ParsedBlocklist},
+    setup,
+    telemetry::init_logging,
+    types::Link,
+};
+use std::{collections::BTreeSet, fs, path::PathBuf};
+use tracing::*;
+
+/// Seed the database with the links from public blocklists, like uBlacklist
+/// subscriptions or the Content Farm Terminator lists
+#[derive(Parser)]
+struct Arguments {
+    /// Recorded on each link, so the same list is never applied twice
+    #[arg(long)]
+    source: String,
+    #[arg(long, value_enum)]
+    format: Format,
+    /// Added to each link's sum of votes, as that many seed votes
+    #[arg(long, default_value_t = -20, allow_negative_numbers = true)]
+    value: i32,
+    /// Parse the lists and report what would be seeded without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    #[value(name = "ublacklist")]
+    UBlacklist,
+    Hosts,
+    Domains,
+}
+impl From<Format> for BlocklistFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::UBlacklist => BlocklistFormat::UBlacklist,
+            Format::Hosts => BlocklistFormat::Hosts,
+            Format::Domains => BlocklistFormat::Domains,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let arguments = Arguments::parse();
+    // Read every list before setting anything up, so a bad file fails early
+    let mut blocklists = vec![];
+    for file in &arguments.files {
+        let parsed = parse_blocklist(&fs::read_to_string(file)?, arguments.format.into());
+        blocklists.push((file, parsed));
+    }
+
+    // A dry run doesn't need the config or the table
+    if arguments.dry_run {
+        init_logging("info", "text");
+        let links = collect_links(&blocklists);
+        for link in &links {
+            println!("{}", link.hostname);
+        }
+        info!(
+            "Dry run, nothing was written [count_of_links={}]",
+            links.len()
+        );
+        return Ok(());
+    }
+
+    let (config, dynamo_db_client) = setup().await;
+    let links = collect_links(&blocklists);
+    let summary = seed_links(
+        &links,
+        &arguments.source,
+        arguments.value,
+        &config,
+        &dynamo_db_client,
+    )
+    .await?;
+    info!(
+        "Seeded links [source={}, count_of_seeded_links={}, count_of_skipped_links={}]",
+        arguments.source, summary.count_of_seeded_links, summary.count_of_skipped_links
+    );
+    Ok(())
+}
+
+// Logs what was parsed from each file, once logging is set up
+fn collect_links(blocklists: &[(&PathBuf, ParsedBlocklist)]) -> BTreeSet<Link> {
+    let mut links = BTreeSet::new();
+    for (file, parsed) in blocklists {
+        for line in &parsed.rejected_lines {
+            warn!("Skipping line [file={}, line={}]", file.display(), line);
+        }
+        info!(
+            "Parsed blocklist [file={}, count_of_links={}, count_of_rejected_lines={}]",
+            file.display(),
+            parsed.links.len(),
+            parsed.rejected_lines.len()
+        );
+        links.extend(parsed.links.iter().cloned());
+    }
+    links
+}
@@ -0,0 +1,43 @@
+use clap::Parser;
+use lambda_http::Error;
+use request_handler::{
+    setup,
+    snapshots::{import_snapshot, verify_snapshot},
+};
+use std::path::PathBuf;
+use tracing::*;
+
+/// Restore a snapshot made by `export-snapshot` into an empty table
+#[derive(Parser)]
+struct Arguments {
+    /// Only check the files against the manifest's counts and checksums
+    #[arg(long)]
+    verify_only: bool,
+    /// The directory with the snapshot's `manifest.json`
+    input_directory: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let arguments = Arguments::parse();
+    let (config, dynamo_db_client) = setup().await;
+
+    let manifest = if arguments.verify_only {
+        verify_snapshot(&arguments.input_directory)?
+    } else {
+        import_snapshot(&arguments.input_directory, &config, &dynamo_db_client).await?
+    };
+    for file in &manifest.files {
+        info!(
+            "{} snapshot file [file_name={}, count_of_items={}]",
+            if arguments.verify_only {
+                "Verified"
+            } else {
+                "Imported"
+            },
+            file.file_name,
+            file.count_of_items
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use request_handler::{clock::clock_from_config, setup, shadow_bans::lift_shadow_ban};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::*;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct LiftShadowBanEvent {
+    user_id: Uuid,
+}
+
+// Invoked by hand, see `LiftShadowBan` in the SAM templates
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let (config, dynamo_db_client) = setup().await;
+    let clock = clock_from_config(&config);
+    info!("Loaded config [{:?}, clock={:?}]", config, clock);
+
+    let (clock, config, dynamo_db_client) = (clock.as_ref(), &config, &dynamo_db_client);
+    run(service_fn(
+        |event: LambdaEvent<LiftShadowBanEvent>| async move {
+            let user_id = event.payload.user_id;
+            let count_of_applied_votes =
+                lift_shadow_ban(&user_id, clock, config, dynamo_db_client).await?;
+            info!(
+                "Lifted shadow ban [user_id={}, count_of_applied_votes={}]",
+                user_id, count_of_applied_votes
+            );
+            Ok::<Value, Error>(json!({ "count_of_applied_votes": count_of_applied_votes }))
+        },
+    ))
+    .await
+}
@@ -0,0 +1,46 @@
+use clap::Parser;
+use lambda_http::Error;
+use request_handler::{
+    clock::clock_from_config,
+    migrations::{run_migrations, MIGRATIONS},
+    setup,
+};
+use tracing::*;
+
+/// Bring every item in the table up to the latest schema version. Safe to run
+/// again, an interrupted run carries on from its last checkpoint.
+#[derive(Parser)]
+struct Arguments {
+    /// Scan the whole table again, even if the latest migration already completed
+    #[arg(long)]
+    restart: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let arguments = Arguments::parse();
+    let (config, dynamo_db_client) = setup().await;
+
+    for migration in &MIGRATIONS {
+        info!(
+            "Migration [version={}, description={}]",
+            migration.version, migration.description
+        );
+    }
+    let progress = run_migrations(
+        arguments.restart,
+        clock_from_config(&config).as_ref(),
+        &config,
+        &dynamo_db_client,
+    )
+    .await?;
+    info!(
+        "Migrations complete [target_version={}, count_of_scanned_items={}, count_of_migrated_items={}, started_at={}, completed_at={:?}]",
+        progress.target_version,
+        progress.count_of_scanned_items,
+        progress.count_of_migrated_items,
+        progress.started_at,
+        progress.completed_at
+    );
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use request_handler::{setup, types::Link, vote_bursts::resolve_link_review};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::*;
+
+#[derive(Deserialize)]
+struct ResolveLinkReviewEvent {
+    link: Link,
+    day: String,
+}
+
+// Invoked by hand, see `ResolveLinkReview` in the SAM templates
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let (config, dynamo_db_client) = setup().await;
+    info!("Loaded config [{:?}]", config);
+
+    let (config, dynamo_db_client) = (&config, &dynamo_db_client);
+    run(service_fn(
+        |event: LambdaEvent<ResolveLinkReviewEvent>| async move {
+            let ResolveLinkReviewEvent { link, day } = event.payload;
+            let is_unfrozen = resolve_link_review(&link, &day, config, dynamo_db_client).await?;
+            info!(
+                "Resolved link review [link={:?}, day={}, is_unfrozen={}]",
+                link, day, is_unfrozen
+            );
+            Ok::<Value, Error>(json!({ "is_unfrozen": is_unfrozen }))
+        },
+    ))
+    .await
+}
@@ -0,0 +1,20 @@
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use request_handler::{clock::clock_from_config, reputation::update_reputations, setup};
+use serde_json::{json, Value};
+use tracing::*;
+
+// Runs on a schedule, see `UpdateReputations` in the SAM templates
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let (config, dynamo_db_client) = setup().await;
+    let clock = clock_from_config(&config);
+    info!("Loaded config [{:?}, clock={:?}]", config, clock);
+
+    run(service_fn(|_event: LambdaEvent<Value>| async {
+        let count_of_updated_users =
+            update_reputations(&config, &dynamo_db_client, clock.now()).await?;
+        info!("Updated reputations [count={}]", count_of_updated_users);
+        Ok::<Value, Error>(json!({ "count_of_updated_users": count_of_updated_users }))
+    }))
+    .await
+}
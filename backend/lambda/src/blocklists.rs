@@ -0,0 +1,272 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    dynamodb::*,
+    types::{
+        database::{LinkDetail, SCHEMA_VERSION},
+        Config, Link,
+    },
+};
+use aws_sdk_dynamodb::{model::AttributeValue::*, types::SdkError, Client};
+use futures::{stream, StreamExt, TryStreamExt};
+use lambda_http::Error;
+use validator::Validate;
+
+// Keeps well below the table's write capacity while still being quick
+const CONCURRENT_WRITES: usize = 25;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BlocklistFormat {
+    /// uBlacklist match patterns like `*://*.example.com/*`
+    UBlacklist,
+    /// Hosts files like `0.0.0.0 example.com`
+    Hosts,
+    /// One domain per line, anything after the domain is ignored
+    Domains,
+}
+
+/// The links in a blocklist along with the lines that couldn't be used
+#[derive(Debug, PartialEq)]
+pub struct ParsedBlocklist {
+    pub links: BTreeSet<Link>,
+    pub rejected_lines: Vec<String>,
+}
+
+pub fn parse_blocklist(contents: &str, format: BlocklistFormat) -> ParsedBlocklist {
+    let mut links = BTreeSet::new();
+    let mut rejected_lines = vec![];
+    for line in contents.lines().map(str::trim) {
+        // Comments, and regex patterns which can't be turned into hostnames
+        if line.is_empty() || line.starts_with(['#', '!', '/']) {
+            continue;
+        }
+        let hostnames = match format {
+            BlocklistFormat::UBlacklist => parse_match_pattern(line),
+            BlocklistFormat::Hosts => parse_hosts_entry(line),
+            BlocklistFormat::Domains => line
+                .split_whitespace()
+                .next()
+                .map(|domain| vec![domain.to_string()]),
+        };
+        match hostnames {
+            Some(hostnames) => {
+                for hostname in hostnames {
+                    match canonical_link(&hostname) {
+                        Some(link) => {
+                            links.insert(link);
+                        }
+                        None => rejected_lines.push(line.to_string()),
+                    }
+                }
+            }
+            None => rejected_lines.push(line.to_string()),
+        }
+    }
+    ParsedBlocklist {
+        links,
+        rejected_lines,
+    }
+}
+
+// `*://*.example.com/*` matches every subdomain, but votes are per hostname so
+// the closest we can get is the domain itself and its `www.`
+fn parse_match_pattern(pattern: &str) -> Option<Vec<String>> {
+    let (_scheme, rest) = pattern.split_once("://")?;
+    let host = rest.split('/').next()?;
+    match host.strip_prefix("*.") {
+        Some(domain) => Some(vec![domain.to_string(), format!("www.{}", domain)]),
+        None if host.contains('*') => None,
+        None => Some(vec![host.to_string()]),
+    }
+}
+
+fn parse_hosts_entry(line: &str) -> Option<Vec<String>> {
+    let mut parts = line.split('#').next()?.split_whitespace();
+    let _address = parts.next()?;
+    let hostnames: Vec<String> = parts
+        .filter(|hostname| {
+            !["localhost", "localhost.localdomain", "broadcasthost"].contains(hostname)
+        })
+        .map(str::to_string)
+        .collect();
+    if hostnames.is_empty() {
+        None
+    } else {
+        Some(hostnames)
+    }
+}
+
+// Lowercase, without a trailing dot, and valid by the same rules as the API.
+// A single label is most likely the first word of a malformed line, and seeding
+// it would give a made up link a score.
+fn canonical_link(hostname: &str) -> Option<Link> {
+    let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+    if !hostname.contains('.') {
+        return None;
+    }
+    let link = Link::new(&hostname);
+    link.validate().ok().map(|_| link)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SeedSummary {
+    pub count_of_seeded_links: u32,
+    /// Links that were already seeded from the same source
+    pub count_of_skipped_links: u32,
+}
+
+/// Add `value` to each link's votes, as `|value|` votes from the seed `source`.
+/// Seed votes are counted separately in `count_of_seed_votes` & `sum_of_seed_votes`
+/// so they can be told apart from organic votes, and each source is only ever
+/// applied once per link so imports can be safely re-run.
+pub async fn seed_links(
+    links: &BTreeSet<Link>,
+    source: &str,
+    value: i32,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<SeedSummary, Error> {
+    let links: Vec<&Link> = links.iter().collect();
+    let mut summary = SeedSummary::default();
+    for chunk in links.chunks(100) {
+        // Existing links need their current sum to start `weighted_sum_of_votes` from
+        let keys = chunk.iter().map(|link| get_link_detail(link)).collect();
+        let mut sums_of_votes = HashMap::new();
+        for item in batch_get_items(keys, config, dynamo_db_client).await? {
+            let link_detail = LinkDetail::try_from(&item)?;
+            sums_of_votes.insert(link_detail.link, link_detail.sum_of_votes);
+        }
+
+        let results: Vec<bool> = stream::iter(chunk.iter())
+            .map(|link| {
+                let sum_of_votes = sums_of_votes.get(*link).copied().unwrap_or(0);
+                seed_link(link, source, value, sum_of_votes, config, dynamo_db_client)
+            })
+            .buffer_unordered(CONCURRENT_WRITES)
+            .try_collect()
+            .await?;
+        for was_seeded in results {
+            if was_seeded {
+                summary.count_of_seeded_links += 1;
+            } else {
+                summary.count_of_skipped_links += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+// Returns false if the link was already seeded from this source
+async fn seed_link(
+    link: &Link,
+    source: &str,
+    value: i32,
+    sum_of_votes: i32,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<bool, Error> {
+    let result = dynamo_db_client
+        .update_item()
+        .table_name(&config.table_name)
+        .set_key(Some(get_link_detail(link)))
+        .update_expression(format!(
+            "SET {},{},{},{},{},{},{} ADD seed_sources :sources",
+            "count_of_votes = if_not_exists(count_of_votes, :zero) + :count",
+            "sum_of_votes = if_not_exists(sum_of_votes, :zero) + :value",
+            "weighted_sum_of_votes = if_not_exists(weighted_sum_of_votes, :sum) + :value",
+            "count_of_seed_votes = if_not_exists(count_of_seed_votes, :zero) + :count",
+            "sum_of_seed_votes = if_not_exists(sum_of_seed_votes, :zero) + :value",
+            "entity_type = :entity_type",
+            "schema_version = if_not_exists(schema_version, :schema_version)",
+        ))
+        .condition_expression(
+            "attribute_not_exists(seed_sources) OR NOT contains(seed_sources, :source)",
+        )
+        .expression_attribute_values(":zero", N(0.to_string()))
+        .expression_attribute_values(":count", N(value.abs().to_string()))
+        .expression_attribute_values(":value", N(value.to_string()))
+        .expression_attribute_values(":sum", N(sum_of_votes.to_string()))
+        .expression_attribute_values(":entity_type", S("LinkDetail".to_string()))
+        .expression_attribute_values(":schema_version", N(SCHEMA_VERSION.to_string()))
+        .expression_attribute_values(":sources", Ss(vec![source.to_string()]))
+        .expression_attribute_values(":source", S(source.to_string()))
+        .send()
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        Err(SdkError::ServiceError(error))
+            if error.err().is_conditional_check_failed_exception() =>
+        {
+            Ok(false)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(hostnames: &[&str]) -> BTreeSet<Link> {
+        hostnames
+            .iter()
+            .map(|hostname| Link::new(hostname))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_blocklist() {
+        let ublacklist = "\
+            # Content farms\n\
+            *://*.example.com/*\n\
+            *://Spam.Example.ORG/*\n\
+            https://clone.net/some/path\n\
+            /example\\.(net|org)/\n\
+            *://partial*.com/*\n\
+            *://spam site.com/*\n";
+        assert_eq!(
+            parse_blocklist(ublacklist, BlocklistFormat::UBlacklist),
+            ParsedBlocklist {
+                links: links(&[
+                    "example.com",
+                    "www.example.com",
+                    "spam.example.org",
+                    "clone.net"
+                ]),
+                rejected_lines: vec![
+                    "*://partial*.com/*".to_string(),
+                    "*://spam site.com/*".to_string()
+                ],
+            }
+        );
+
+        let hosts = "\
+            127.0.0.1 localhost\n\
+            0.0.0.0 ads.example.com tracker.example.com # Trackers\n\
+            0.0.0.0 not_a_hostname\n";
+        assert_eq!(
+            parse_blocklist(hosts, BlocklistFormat::Hosts),
+            ParsedBlocklist {
+                links: links(&["ads.example.com", "tracker.example.com"]),
+                rejected_lines: vec![
+                    "127.0.0.1 localhost".to_string(),
+                    "0.0.0.0 not_a_hostname".to_string()
+                ],
+            }
+        );
+
+        let domains = "\
+            ! Nearly content farms\n\
+            farm.example.com   Copies of Stack Overflow\n\
+            farm.example.com.\n\
+            \n\
+            bad domain\n";
+        assert_eq!(
+            parse_blocklist(domains, BlocklistFormat::Domains),
+            ParsedBlocklist {
+                links: links(&["farm.example.com"]),
+                rejected_lines: vec!["bad domain".to_string()],
+            }
+        );
+    }
+}
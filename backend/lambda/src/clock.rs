@@ -0,0 +1,87 @@
+use std::{fmt::Debug, sync::Mutex};
+
+use crate::types::Config;
+use chrono::{DateTime, Duration, Utc};
+
+/// Where the handlers get the current time from, so tests can control it
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// The fake clock behind this one, if it can be changed
+    fn fake(&self) -> Option<&FakeClock> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when it's told to
+#[derive(Debug)]
+pub struct FakeClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        FakeClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+
+    fn fake(&self) -> Option<&FakeClock> {
+        Some(self)
+    }
+}
+
+/// A fake clock starting at `fake_time` if it's set, for reproducible tests,
+/// otherwise the system clock
+pub fn clock_from_config(config: &Config) -> Box<dyn Clock> {
+    match config.fake_time {
+        Some(fake_time) => Box::new(FakeClock::new(fake_time)),
+        None => Box::new(SystemClock),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_clock() {
+        let start = DateTime::parse_from_rfc3339("2022-07-27T12:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = FakeClock::new(start);
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::days(1));
+        assert_eq!(clock.now(), start + Duration::days(1));
+
+        clock.set(start);
+        assert_eq!(clock.now(), start);
+        assert!(clock.fake().is_some());
+        assert!(SystemClock.fake().is_none());
+    }
+}
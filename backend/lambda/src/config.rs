@@ -0,0 +1,310 @@
+use std::{collections::HashMap, env, fmt, fs, str::FromStr};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    scoring::{parse_random_score_weights, RandomScores},
+    types::Config,
+};
+
+/// Kept out of the logs when the config is printed
+#[derive(Clone, PartialEq)]
+pub struct AdminKey(pub String);
+impl fmt::Debug for AdminKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AdminKey(..)")
+    }
+}
+
+const MINIMUM_ADMIN_KEY_LENGTH: usize = 16;
+
+/// Every setting that's missing or invalid, so they can all be fixed at once
+#[derive(Debug, PartialEq)]
+pub struct ConfigErrors(pub Vec<String>);
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration")?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for ConfigErrors {}
+
+/// Load the config from the environment, and from the TOML file in `CONFIG_FILE`
+/// if it's set
+pub fn load_config() -> Result<Config, ConfigErrors> {
+    let file = match env::var("CONFIG_FILE") {
+        Ok(path) => Some(fs::read_to_string(&path).map_err(|e| {
+            ConfigErrors(vec![format!("CONFIG_FILE {} can't be read: {}", path, e)])
+        })?),
+        Err(_) => None,
+    };
+    config_from_sources(env::vars().collect(), file.as_deref())
+}
+
+/// Each setting comes from the environment variable if it's set, otherwise from
+/// the file under the lower case name, e.g. `TABLE_NAME` or `table_name`
+pub fn config_from_sources(
+    env: HashMap<String, String>,
+    file: Option<&str>,
+) -> Result<Config, ConfigErrors> {
+    let file = match file {
+        Some(file) => file
+            .parse::<toml::Table>()
+            .map_err(|e| ConfigErrors(vec![format!("CONFIG_FILE is not valid TOML: {}", e)]))?,
+        None => toml::Table::new(),
+    };
+    let mut settings = Settings {
+        env,
+        file,
+        errors: vec![],
+    };
+
+    let random_scores = RandomScores {
+        seed: settings.optional("RANDOM_SCORES_SEED", "a number", 0),
+        weights: settings.parsed_with(
+            "RANDOM_SCORES_WEIGHTS",
+            parse_random_score_weights,
+            RandomScores::default().weights,
+        ),
+    };
+    let config = Config {
+        table_name: settings.required("TABLE_NAME", "a table name"),
+        log_level: settings.optional("LOG_LEVEL", "a log filter", "info".to_string()),
+        log_format: settings.parsed_with("LOG_FORMAT", parse_log_format, "json".to_string()),
+        // Optional so existing environments keep scoring on the raw sum of votes
+        use_weighted_scores: settings.optional("USE_WEIGHTED_SCORES", "a boolean", false),
+        cors_allowed_origins: settings.parsed_with(
+            "CORS_ALLOWED_ORIGINS",
+            parse_origins,
+            vec!["*".to_string()],
+        ),
+        scores_cache_max_age: settings.optional("SCORES_CACHE_MAX_AGE", "a number of seconds", 0),
+        filter_list_cache_max_age: settings.optional(
+            "FILTER_LIST_CACHE_MAX_AGE",
+            "a number of seconds",
+            3600,
+        ),
+        compress_responses: settings.optional("COMPRESS_RESPONSES", "a boolean", false),
+        maximum_queued_vote_age_in_days: settings.optional(
+            "MAXIMUM_QUEUED_VOTE_AGE_IN_DAYS",
+            "a number of days",
+            7,
+        ),
+        admin_key: settings.optional_parsed_with("ADMIN_KEY", parse_admin_key),
+        rate_limit_per_minute: settings.optional(
+            "RATE_LIMIT_PER_MINUTE",
+            "a number of requests",
+            0,
+        ),
+        // The following are for testing & development
+        use_local_database: settings.optional("USE_LOCAL_DATABASE", "a boolean", false),
+        randomize_scores: settings.optional("RANDOMIZE_SCORES", "a boolean", false),
+        random_scores,
+        fake_time: settings.optional_parsed_with("FAKE_TIME", parse_time),
+    };
+
+    match settings.errors.is_empty() {
+        true => Ok(config),
+        false => Err(ConfigErrors(settings.errors)),
+    }
+}
+
+struct Settings {
+    env: HashMap<String, String>,
+    file: toml::Table,
+    errors: Vec<String>,
+}
+impl Settings {
+    fn value(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.env.get(name) {
+            return Some(value.clone());
+        }
+        let as_string = |value: &toml::Value| match value {
+            toml::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        self.file
+            .get(&name.to_lowercase())
+            .map(|value| match value {
+                toml::Value::Array(values) => {
+                    values.iter().map(as_string).collect::<Vec<_>>().join(",")
+                }
+                value => as_string(value),
+            })
+    }
+
+    // Missing and invalid settings are recorded, and the default keeps loading going
+    fn required<T: FromStr + Default>(&mut self, name: &str, expected: &str) -> T {
+        match self.value(name) {
+            Some(_) => self.optional(name, expected, T::default()),
+            None => {
+                self.errors.push(format!("{} should be set", name));
+                T::default()
+            }
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, name: &str, expected: &str, default: T) -> T {
+        self.parsed_with(
+            name,
+            |value| {
+                value
+                    .parse::<T>()
+                    .or(Err(format!("should be {}", expected)))
+            },
+            default,
+        )
+    }
+
+    fn parsed_with<T>(
+        &mut self,
+        name: &str,
+        parse: impl Fn(&str) -> Result<T, String>,
+        default: T,
+    ) -> T {
+        self.optional_parsed_with(name, parse).unwrap_or(default)
+    }
+
+    fn optional_parsed_with<T>(
+        &mut self,
+        name: &str,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> Option<T> {
+        let value = self.value(name)?;
+        match parse(value.trim()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.push(format!("{} {}", name, e));
+                None
+            }
+        }
+    }
+}
+
+fn parse_origins(origins: &str) -> Result<Vec<String>, String> {
+    let origins: Vec<String> = origins
+        .split(',')
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    match origins.is_empty() {
+        true => Err("should have at least one origin".to_string()),
+        false => Ok(origins),
+    }
+}
+
+fn parse_log_format(log_format: &str) -> Result<String, String> {
+    match log_format {
+        "json" | "text" => Ok(log_format.to_string()),
+        _ => Err("should be json or text".to_string()),
+    }
+}
+
+fn parse_admin_key(admin_key: &str) -> Result<AdminKey, String> {
+    match admin_key.len() >= MINIMUM_ADMIN_KEY_LENGTH {
+        true => Ok(AdminKey(admin_key.to_string())),
+        false => Err(format!(
+            "should be at least {} characters",
+            MINIMUM_ADMIN_KEY_LENGTH
+        )),
+    }
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .or(Err("should be an RFC 3339 timestamp".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Score;
+
+    fn env(variables: &[(&str, &str)]) -> HashMap<String, String> {
+        variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = config_from_sources(env(&[("TABLE_NAME", "Discontent")]), None).unwrap();
+        assert_eq!(config.table_name, "Discontent");
+        assert_eq!(config.log_level, "info");
+        assert_eq!(config.log_format, "json");
+        assert!(!config.use_weighted_scores);
+        assert_eq!(config.cors_allowed_origins, vec!["*"]);
+        assert_eq!(config.filter_list_cache_max_age, 3600);
+        assert!(!config.compress_responses);
+        assert_eq!(config.maximum_queued_vote_age_in_days, 7);
+        assert_eq!(config.admin_key, None);
+        assert_eq!(config.rate_limit_per_minute, 0);
+        assert_eq!(config.random_scores, RandomScores::default());
+        assert_eq!(config.fake_time, None);
+    }
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let file = r#"
+            table_name = "FromFile"
+            use_weighted_scores = true
+            cors_allowed_origins = ["chrome-extension://abc", "moz-extension://def"]
+            scores_cache_max_age = 60
+            random_scores_weights = "Bad=1"
+        "#;
+        let config = config_from_sources(
+            env(&[
+                ("TABLE_NAME", "FromEnvironment"),
+                ("ADMIN_KEY", "0123456789abcdef"),
+                ("FAKE_TIME", "2022-07-27T12:30:00Z"),
+            ]),
+            Some(file),
+        )
+        .unwrap();
+        assert_eq!(config.table_name, "FromEnvironment");
+        assert!(config.use_weighted_scores);
+        assert_eq!(
+            config.cors_allowed_origins,
+            vec!["chrome-extension://abc", "moz-extension://def"]
+        );
+        assert_eq!(config.scores_cache_max_age, 60);
+        assert_eq!(config.random_scores.weights, vec![(Score::Bad, 1)]);
+        assert_eq!(
+            config.admin_key,
+            Some(AdminKey("0123456789abcdef".to_string()))
+        );
+        assert!(!format!("{:?}", config).contains("0123456789abcdef"));
+        assert_eq!(
+            config.fake_time.unwrap().to_rfc3339(),
+            "2022-07-27T12:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_every_error_is_reported() {
+        let errors = config_from_sources(
+            env(&[
+                ("USE_LOCAL_DATABASE", "yes"),
+                ("FILTER_LIST_CACHE_MAX_AGE", "-1"),
+                ("ADMIN_KEY", "short"),
+            ]),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            ConfigErrors(vec![
+                "TABLE_NAME should be set".to_string(),
+                "FILTER_LIST_CACHE_MAX_AGE should be a number of seconds".to_string(),
+                "ADMIN_KEY should be at least 16 characters".to_string(),
+                "USE_LOCAL_DATABASE should be a boolean".to_string(),
+            ])
+        );
+        assert!(config_from_sources(env(&[]), Some("table_name = ")).is_err());
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::types::{database::Vote, Config, Dimension, Link, VoteReason};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, AttributeValue::*},
+    types::SdkError,
+    Client,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use lambda_http::Error;
+use uuid::Uuid;
+
+// Keeps well below the table's write capacity while still being quick
+const CONCURRENT_WRITES: usize = 25;
+
+/// An item whose `count_of_votes` & `sum_of_votes` are kept up to date as votes
+/// come in, along with the other aggregates on a `LinkDetail`
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
+pub enum Aggregate {
+    LinkDetail { link: Link },
+    LinkHistory { day: String, link: Link },
+    UserHistory { day: String, user_id: Uuid },
+}
+impl Aggregate {
+    fn key(&self) -> HashMap<String, AttributeValue> {
+        let (partition_key, sort_key) = match self {
+            Aggregate::LinkDetail { link } => (
+                format!("link#{}", link.hostname),
+                format!("link#{}", link.hostname),
+            ),
+            Aggregate::LinkHistory { day, link } => {
+                (format!("day#{}", day), format!("link#{}", link.hostname))
+            }
+            Aggregate::UserHistory { day, user_id } => (
+                format!("day#{}", day),
+                format!("user#{}", user_id.hyphenated()),
+            ),
+        };
+        HashMap::from([
+            ("PK".to_string(), S(partition_key)),
+            ("SK".to_string(), S(sort_key)),
+        ])
+    }
+
+    fn entity_type(&self) -> &'static str {
+        match self {
+            Aggregate::LinkDetail { .. } => "LinkDetail",
+            Aggregate::LinkHistory { .. } => "LinkHistory",
+            Aggregate::UserHistory { .. } => "UserHistory",
+        }
+    }
+}
+
+/// Signed, because a drifted count can go below zero. Only a `LinkDetail` has
+/// the weighted sum, reasons & dimensions, they're left empty on history items.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Totals {
+    pub count_of_votes: i64,
+    pub sum_of_votes: i64,
+    /// In hundredths, the precision each weighted vote is stored with
+    pub weighted_sum_of_votes: i64,
+    /// Reasons without any votes are left out
+    pub count_of_reasons: BTreeMap<VoteReason, i64>,
+    /// `(count_of_votes, sum_of_votes)`, leaving out dimensions without any votes
+    pub dimensions: BTreeMap<Dimension, (i64, i64)>,
+}
+impl Totals {
+    fn add(&mut self, count_of_votes: i64, sum_of_votes: i64) {
+        self.count_of_votes += count_of_votes;
+        self.sum_of_votes += sum_of_votes;
+    }
+
+    // Everything a vote adds to its link
+    fn add_vote(&mut self, vote: &Vote) {
+        let value = vote.value as i64;
+        self.add(1, value);
+        self.weighted_sum_of_votes += hundredths(vote.value as f64 * vote.weight);
+        if let Some(reason) = vote.reason {
+            *self.count_of_reasons.entry(reason).or_default() += 1;
+        }
+        if let Some(dimension) = vote.dimension {
+            let (count_of_votes, sum_of_votes) = self.dimensions.entry(dimension).or_default();
+            *count_of_votes += 1;
+            *sum_of_votes += value;
+        }
+    }
+}
+
+fn hundredths(value: f64) -> i64 {
+    (value * 100.0).round() as i64
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Discrepancy {
+    pub aggregate: Aggregate,
+    pub expected: Totals,
+    /// `None` if the item doesn't exist
+    pub stored: Option<Totals>,
+}
+
+/// What the aggregates should be given the current votes. Each vote counts
+/// towards the day it was made, and seed votes only exist on the `LinkDetail`,
+/// where they're weighted as 1 and have no reason or dimension.
+pub fn expected_totals(
+    votes: &[Vote],
+    seed_totals: &HashMap<Link, Totals>,
+) -> HashMap<Aggregate, Totals> {
+    let mut expected: HashMap<Aggregate, Totals> = HashMap::new();
+    for (link, totals) in seed_totals {
+        let link_totals = expected
+            .entry(Aggregate::LinkDetail { link: link.clone() })
+            .or_default();
+        link_totals.add(totals.count_of_votes, totals.sum_of_votes);
+        link_totals.weighted_sum_of_votes += totals.sum_of_votes * 100;
+    }
+    for vote in votes {
+        let day = vote.created_at[..10].to_string();
+        let value = vote.value as i64;
+        expected
+            .entry(Aggregate::LinkDetail {
+                link: vote.link.clone(),
+            })
+            .or_default()
+            .add_vote(vote);
+        for aggregate in [
+            Aggregate::LinkHistory {
+                day: day.clone(),
+                link: vote.link.clone(),
+            },
+            Aggregate::UserHistory {
+                day,
+                user_id: vote.user_id,
+            },
+        ] {
+            expected.entry(aggregate).or_default().add(1, value);
+        }
+    }
+    expected
+}
+
+/// Aggregates that don't match their votes, sorted so links and days are grouped.
+/// Missing items are the same as zero, since reverted history items stay at zero.
+pub fn find_discrepancies(
+    expected: &HashMap<Aggregate, Totals>,
+    stored: &HashMap<Aggregate, Totals>,
+) -> Vec<Discrepancy> {
+    let aggregates: BTreeSet<&Aggregate> = expected.keys().chain(stored.keys()).collect();
+    aggregates
+        .into_iter()
+        .filter_map(|aggregate| {
+            let expected = expected.get(aggregate).cloned().unwrap_or_default();
+            let stored = stored.get(aggregate).cloned();
+            if expected == stored.clone().unwrap_or_default() {
+                return None;
+            }
+            Some(Discrepancy {
+                aggregate: aggregate.clone(),
+                expected,
+                stored,
+            })
+        })
+        .collect()
+}
+
+/// Recompute every aggregate from the `Vote` items and compare them with the
+/// stored `LinkDetail`, `LinkHistory` & `UserHistory` items
+pub async fn check_consistency(
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<Discrepancy>, Error> {
+    let mut votes = vec![];
+    let mut seed_totals = HashMap::new();
+    let mut stored = HashMap::new();
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .scan()
+            .table_name(&config.table_name)
+            .consistent_read(true)
+            .filter_expression("entity_type IN (:vote, :link_detail, :link_history, :user_history)")
+            .expression_attribute_values(":vote", S("Vote".to_string()))
+            .expression_attribute_values(":link_detail", S("LinkDetail".to_string()))
+            .expression_attribute_values(":link_history", S("LinkHistory".to_string()))
+            .expression_attribute_values(":user_history", S("UserHistory".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            let entity_type = item
+                .get("entity_type")
+                .ok_or("No entity_type")?
+                .as_s()
+                .or(Err("entity_type is not a string"))?;
+            if entity_type == "Vote" {
+                votes.push(Vote::try_from(item)?);
+                continue;
+            }
+            let aggregate = aggregate_from_item(entity_type, item)?;
+            let mut totals = Totals {
+                count_of_votes: number(item, "count_of_votes")?,
+                sum_of_votes: number(item, "sum_of_votes")?,
+                ..Default::default()
+            };
+            if let Aggregate::LinkDetail { link } = &aggregate {
+                add_link_detail_totals(&mut totals, item)?;
+                seed_totals.insert(
+                    link.clone(),
+                    Totals {
+                        count_of_votes: number(item, "count_of_seed_votes")?,
+                        sum_of_votes: number(item, "sum_of_seed_votes")?,
+                        ..Default::default()
+                    },
+                );
+            }
+            stored.insert(aggregate, totals);
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    Ok(find_discrepancies(
+        &expected_totals(&votes, &seed_totals),
+        &stored,
+    ))
+}
+
+fn aggregate_from_item(
+    entity_type: &str,
+    item: &HashMap<String, AttributeValue>,
+) -> Result<Aggregate, Error> {
+    let key_suffix = |name: &str| -> Result<String, Error> {
+        Ok(item
+            .get(name)
+            .ok_or(format!("No {}", name))?
+            .as_s()
+            .or(Err(format!("{} is not a string", name)))?
+            .split_once('#')
+            .ok_or(format!("Malformed {}", name))?
+            .1
+            .to_string())
+    };
+    Ok(match entity_type {
+        "LinkDetail" => Aggregate::LinkDetail {
+            link: Link::new(&key_suffix("PK")?),
+        },
+        "LinkHistory" => Aggregate::LinkHistory {
+            day: key_suffix("PK")?,
+            link: Link::new(&key_suffix("SK")?),
+        },
+        _ => Aggregate::UserHistory {
+            day: key_suffix("PK")?,
+            user_id: Uuid::parse_str(&key_suffix("SK")?)?,
+        },
+    })
+}
+
+// Missing numbers count as zero
+fn number(item: &HashMap<String, AttributeValue>, name: &str) -> Result<i64, Error> {
+    match item.get(name) {
+        Some(value) => Ok(value
+            .as_n()
+            .or(Err(format!("{} is not a number", name)))?
+            .parse::<i64>()?),
+        None => Ok(0),
+    }
+}
+
+// Links from before weighting are migrated with the raw sum, so that's what a
+// missing `weighted_sum_of_votes` is read as
+fn add_link_detail_totals(
+    totals: &mut Totals,
+    item: &HashMap<String, AttributeValue>,
+) -> Result<(), Error> {
+    totals.weighted_sum_of_votes = match item.get("weighted_sum_of_votes") {
+        Some(value) => hundredths(
+            value
+                .as_n()
+                .or(Err("weighted_sum_of_votes is not a number"))?
+                .parse::<f64>()?,
+        ),
+        None => totals.sum_of_votes * 100,
+    };
+    for reason in VoteReason::ALL {
+        let count_of_votes = number(item, reason.count_attribute())?;
+        if count_of_votes != 0 {
+            totals.count_of_reasons.insert(reason, count_of_votes);
+        }
+    }
+    for dimension in Dimension::ALL {
+        let count_of_votes = number(item, dimension.count_attribute())?;
+        let sum_of_votes = number(item, dimension.sum_attribute())?;
+        if count_of_votes != 0 || sum_of_votes != 0 {
+            totals
+                .dimensions
+                .insert(dimension, (count_of_votes, sum_of_votes));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RepairSummary {
+    pub count_of_repaired_items: u32,
+    /// Items that changed since they were checked, most likely from a new vote
+    pub count_of_skipped_items: u32,
+}
+
+/// Overwrite each aggregate with the totals it should have. An item is only
+/// written if it still has the totals it was checked with, so votes that come
+/// in during a repair aren't lost, but it's best to disable voting first.
+pub async fn repair_discrepancies(
+    discrepancies: &[Discrepancy],
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<RepairSummary, Error> {
+    let results: Vec<bool> = stream::iter(discrepancies)
+        .map(|discrepancy| repair_discrepancy(discrepancy, config, dynamo_db_client))
+        .buffer_unordered(CONCURRENT_WRITES)
+        .try_collect()
+        .await?;
+    let mut summary = RepairSummary::default();
+    for was_repaired in results {
+        if was_repaired {
+            summary.count_of_repaired_items += 1;
+        } else {
+            summary.count_of_skipped_items += 1;
+        }
+    }
+    Ok(summary)
+}
+
+// Returns false if the item changed since it was checked
+async fn repair_discrepancy(
+    discrepancy: &Discrepancy,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<bool, Error> {
+    let Discrepancy {
+        aggregate,
+        expected,
+        stored,
+    } = discrepancy;
+    let mut set_expressions = vec![
+        "count_of_votes = :count_of_votes".to_string(),
+        "sum_of_votes = :sum_of_votes".to_string(),
+        "entity_type = :entity_type".to_string(),
+    ];
+    let mut remove_attributes = vec![];
+    let mut request = dynamo_db_client
+        .update_item()
+        .table_name(&config.table_name)
+        .set_key(Some(aggregate.key()))
+        .expression_attribute_values(":count_of_votes", N(expected.count_of_votes.to_string()))
+        .expression_attribute_values(":sum_of_votes", N(expected.sum_of_votes.to_string()))
+        .expression_attribute_values(":entity_type", S(aggregate.entity_type().to_string()));
+    match aggregate {
+        // Reasons & dimensions that shouldn't have any votes are removed
+        Aggregate::LinkDetail { .. } => {
+            set_expressions.push("weighted_sum_of_votes = :weighted_sum_of_votes".to_string());
+            request = request.expression_attribute_values(
+                ":weighted_sum_of_votes",
+                N(format!(
+                    "{:.2}",
+                    expected.weighted_sum_of_votes as f64 / 100.0
+                )),
+            );
+            let mut counts = vec![];
+            for reason in VoteReason::ALL {
+                counts.push((
+                    reason.count_attribute(),
+                    expected.count_of_reasons.get(&reason).copied(),
+                ));
+            }
+            for dimension in Dimension::ALL {
+                let totals = expected.dimensions.get(&dimension);
+                counts.push((
+                    dimension.count_attribute(),
+                    totals.map(|(count_of_votes, _)| *count_of_votes),
+                ));
+                counts.push((
+                    dimension.sum_attribute(),
+                    totals.map(|(_, sum_of_votes)| *sum_of_votes),
+                ));
+            }
+            for (attribute, value) in counts {
+                match value {
+                    Some(value) => {
+                        set_expressions.push(format!("{0} = :{0}", attribute));
+                        request = request.expression_attribute_values(
+                            format!(":{}", attribute),
+                            N(value.to_string()),
+                        );
+                    }
+                    None => remove_attributes.push(attribute),
+                }
+            }
+        }
+        // The daily leaderboard indexes need these on new history items
+        Aggregate::LinkHistory { day, .. } => {
+            set_expressions.push("DailyLinkHistory_PK = :day".to_string());
+            request = request.expression_attribute_values(":day", S(format!("day#{}", day)));
+        }
+        Aggregate::UserHistory { day, .. } => {
+            set_expressions.push("DailyUserHistory_PK = :day".to_string());
+            request = request.expression_attribute_values(":day", S(format!("day#{}", day)));
+        }
+    }
+    request = match stored {
+        Some(stored) => request
+            .condition_expression(
+                "count_of_votes = :stored_count_of_votes AND sum_of_votes = :stored_sum_of_votes",
+            )
+            .expression_attribute_values(
+                ":stored_count_of_votes",
+                N(stored.count_of_votes.to_string()),
+            )
+            .expression_attribute_values(
+                ":stored_sum_of_votes",
+                N(stored.sum_of_votes.to_string()),
+            ),
+        None => request.condition_expression("attribute_not_exists(PK)"),
+    };
+    let mut update_expression = format!("SET {}", set_expressions.join(","));
+    if !remove_attributes.is_empty() {
+        update_expression += &format!(" REMOVE {}", remove_attributes.join(","));
+    }
+    let result = request.update_expression(update_expression).send().await;
+    match result {
+        Ok(_) => Ok(true),
+        Err(SdkError::ServiceError(error))
+            if error.err().is_conditional_check_failed_exception() =>
+        {
+            Ok(false)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(hostname: &str, value: i32, user: u128, created_at: &str) -> Vote {
+        Vote {
+            link: Link::new(hostname),
+            value,
+            user_id: Uuid::from_u128(user),
+            created_at: created_at.to_string(),
+            reason: None,
+            dimension: None,
+            weight: 1.0,
+        }
+    }
+
+    fn totals(count_of_votes: i64, sum_of_votes: i64) -> Totals {
+        Totals {
+            count_of_votes,
+            sum_of_votes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_discrepancies() {
+        let link = Link::new("example.com");
+        let link_history = |day: &str| Aggregate::LinkHistory {
+            day: day.to_string(),
+            link: link.clone(),
+        };
+        let user_history = |day: &str, user: u128| Aggregate::UserHistory {
+            day: day.to_string(),
+            user_id: Uuid::from_u128(user),
+        };
+        let mut downvote = vote("example.com", -1, 2, "2023-02-08T11:00:00Z");
+        downvote.reason = Some(VoteReason::SeoSpam);
+        downvote.dimension = Some(Dimension::AdIntrusiveness);
+        downvote.weight = 0.333;
+        let votes = vec![
+            vote("example.com", 1, 1, "2023-02-08T10:00:00Z"),
+            downvote,
+            vote("example.com", 1, 3, "2023-02-09T09:00:00Z"),
+        ];
+        let seed_totals = HashMap::from([(link.clone(), totals(20, -20))]);
+        let expected = expected_totals(&votes, &seed_totals);
+        let link_detail = Totals {
+            count_of_votes: 23,
+            sum_of_votes: -19,
+            weighted_sum_of_votes: -1833,
+            count_of_reasons: BTreeMap::from([(VoteReason::SeoSpam, 1)]),
+            dimensions: BTreeMap::from([(Dimension::AdIntrusiveness, (1, -1))]),
+        };
+        assert_eq!(
+            expected[&Aggregate::LinkDetail { link: link.clone() }],
+            link_detail
+        );
+        assert_eq!(expected[&link_history("2023-02-08")], totals(2, 0));
+        assert_eq!(expected[&user_history("2023-02-09", 3)], totals(1, 1));
+
+        // The counts are right, but a changed vote left its old reason behind
+        let mut stored_link_detail = link_detail.clone();
+        stored_link_detail
+            .count_of_reasons
+            .insert(VoteReason::Malware, 1);
+        let stored = HashMap::from([
+            (
+                Aggregate::LinkDetail { link: link.clone() },
+                stored_link_detail.clone(),
+            ),
+            (link_history("2023-02-08"), totals(2, 0)),
+            // Left behind when a vote moved to a later day
+            (link_history("2023-02-07"), totals(0, 0)),
+            // Drifted from a vote that no longer exists
+            (link_history("2023-02-06"), totals(1, -1)),
+            (user_history("2023-02-08", 1), totals(1, 1)),
+            (user_history("2023-02-08", 2), totals(1, -1)),
+        ]);
+        assert_eq!(
+            find_discrepancies(&expected, &stored),
+            vec![
+                Discrepancy {
+                    aggregate: Aggregate::LinkDetail { link: link.clone() },
+                    expected: link_detail,
+                    stored: Some(stored_link_detail),
+                },
+                Discrepancy {
+                    aggregate: link_history("2023-02-06"),
+                    expected: totals(0, 0),
+                    stored: Some(totals(1, -1)),
+                },
+                Discrepancy {
+                    aggregate: link_history("2023-02-09"),
+                    expected: totals(1, 1),
+                    stored: None,
+                },
+                Discrepancy {
+                    aggregate: user_history("2023-02-09", 3),
+                    expected: totals(1, 1),
+                    stored: None,
+                },
+            ]
+        );
+    }
+}
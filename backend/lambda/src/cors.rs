@@ -0,0 +1,161 @@
+use crate::types::Config;
+use lambda_http::{
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD,
+            ORIGIN, VARY,
+        },
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    Body, Request, Response,
+};
+
+const ALLOWED_METHODS: &str = "GET, POST, OPTIONS";
+const ALLOWED_HEADERS: &str = "Accept, Accept-Encoding, Authorization, Content-Type";
+// Browsers cap this anyway, Chrome at 2 hours
+const PREFLIGHT_MAX_AGE_IN_SECONDS: u32 = 7200;
+
+/// An origin is allowed if it's in the allowlist, or matches an entry ending in
+/// `*` like `moz-extension://*`, since Firefox gives every install its own id
+pub fn is_origin_allowed(origin: &str, allowed_origins: &[String]) -> bool {
+    allowed_origins
+        .iter()
+        .any(|allowed| match allowed.strip_suffix('*') {
+            Some(prefix) => origin.starts_with(prefix),
+            None => origin == allowed,
+        })
+}
+
+pub fn is_preflight(request: &Request) -> bool {
+    request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Answers a preflight request for any route. The origin is checked by
+/// `add_cors_headers` like any other response.
+pub fn preflight_response() -> Response<Body> {
+    let mut response = Response::new(Body::Empty);
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static(ALLOWED_METHODS),
+    );
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static(ALLOWED_HEADERS),
+    );
+    headers.insert(ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_IN_SECONDS.into());
+    response
+}
+
+/// Lets the requesting origin read the response if it's allowed. Every response
+/// goes through here, whatever its status.
+pub fn add_cors_headers(
+    request_headers: &HeaderMap,
+    response: &mut Response<Body>,
+    config: &Config,
+) {
+    let headers = response.headers_mut();
+    if config
+        .cors_allowed_origins
+        .iter()
+        .any(|allowed| allowed == "*")
+    {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        return;
+    }
+    // The response depends on the origin, so caches need to keep them apart
+    headers.append(VARY, HeaderValue::from_static("Origin"));
+    if let Some(origin) = request_headers.get(ORIGIN) {
+        let allowed = origin
+            .to_str()
+            .map(|origin| is_origin_allowed(origin, &config.cors_allowed_origins))
+            .unwrap_or(false);
+        if allowed {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config_from_sources;
+    use std::collections::HashMap;
+
+    fn config(allowed_origins: &str) -> Config {
+        config_from_sources(
+            HashMap::from([
+                ("TABLE_NAME".to_string(), "Discontent".to_string()),
+                (
+                    "CORS_ALLOWED_ORIGINS".to_string(),
+                    allowed_origins.to_string(),
+                ),
+            ]),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn allowed_origin(origin: &str, config: &Config) -> Option<HeaderValue> {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ORIGIN, HeaderValue::from_str(origin).unwrap());
+        let mut response = Response::new(Body::Empty);
+        add_cors_headers(&request_headers, &mut response, config);
+        response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).cloned()
+    }
+
+    #[test]
+    fn test_allowed_origins() {
+        let extensions =
+            config("chrome-extension://kglbdhongcfkafgfgofpgaehafnbgnhd,moz-extension://*");
+        assert_eq!(
+            allowed_origin(
+                "chrome-extension://kglbdhongcfkafgfgofpgaehafnbgnhd",
+                &extensions
+            )
+            .unwrap(),
+            "chrome-extension://kglbdhongcfkafgfgofpgaehafnbgnhd"
+        );
+        assert_eq!(
+            allowed_origin("moz-extension://3f504997-80b7-467d-9d7b", &extensions).unwrap(),
+            "moz-extension://3f504997-80b7-467d-9d7b"
+        );
+        assert_eq!(
+            allowed_origin("chrome-extension://other", &extensions),
+            None
+        );
+        assert_eq!(allowed_origin("https://example.com", &extensions), None);
+
+        assert_eq!(
+            allowed_origin("https://example.com", &config("*")).unwrap(),
+            "*"
+        );
+    }
+
+    #[test]
+    fn test_preflight_response() {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ORIGIN, HeaderValue::from_static("moz-extension://abc"));
+        let mut response = preflight_response();
+        add_cors_headers(
+            &request_headers,
+            &mut response,
+            &config("moz-extension://*"),
+        );
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_METHODS],
+            ALLOWED_METHODS
+        );
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "moz-extension://abc"
+        );
+        assert_eq!(response.headers()[VARY], "Origin");
+    }
+}
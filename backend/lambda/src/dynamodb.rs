@@ -1,11 +1,132 @@
-use aws_sdk_dynamodb::model::{
-    AttributeValue::{self, *},
-    *,
+use aws_sdk_dynamodb::{
+    model::{
+        AttributeValue::{self, *},
+        *,
+    },
+    Client,
 };
+use lambda_http::Error;
 use std::collections::HashMap;
+use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::types::{database::Vote, Config, Link};
+use crate::{
+    ban_rules::{BanAction, BanDecision},
+    telemetry::{dynamodb_span, record_consumed_capacity},
+    types::{
+        database::{
+            to_item, DailyStats, FilterListPage, LinkStats, MigrationProgress, User, UserVote,
+            Vote, SCHEMA_VERSION,
+        },
+        Config, Link, Score,
+    },
+    vote_bursts::VoteBurst,
+};
+
+/// Get any number of items, splitting them into batches and retrying unprocessed keys.
+/// Items that don't exist are left out of the result.
+pub async fn batch_get_items(
+    keys: Vec<HashMap<String, AttributeValue>>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<HashMap<String, AttributeValue>>, Error> {
+    let mut items = vec![];
+    // BatchGetItem is limited to 100 keys per request
+    for chunk in keys.chunks(100) {
+        let mut keys = chunk.to_vec();
+        while !keys.is_empty() {
+            let span = dynamodb_span("BatchGetItem");
+            let response = dynamo_db_client
+                .batch_get_item()
+                .request_items(
+                    &config.table_name,
+                    KeysAndAttributes::builder().set_keys(Some(keys)).build(),
+                )
+                .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                .send()
+                .instrument(span.clone())
+                .await?;
+            record_consumed_capacity(&span, response.consumed_capacity().unwrap_or_default());
+            items.extend(
+                response
+                    .responses()
+                    .and_then(|responses| responses.get(&config.table_name))
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+            keys = response
+                .unprocessed_keys()
+                .and_then(|unprocessed| unprocessed.get(&config.table_name))
+                .and_then(|unprocessed| unprocessed.keys())
+                .map(|keys| keys.to_vec())
+                .unwrap_or_default();
+        }
+    }
+    Ok(items)
+}
+
+/// Every user in the table, for the scheduled jobs
+pub async fn scan_users(
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<(Uuid, User)>, Error> {
+    let mut users = vec![];
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .scan()
+            .table_name(&config.table_name)
+            .filter_expression("entity_type = :entity_type")
+            .expression_attribute_values(":entity_type", S("User".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            let primary_key = item
+                .get("PK")
+                .ok_or("No PK")?
+                .as_s()
+                .or(Err("PK is not a string"))?;
+            let user_id = Uuid::parse_str(primary_key.split('#').nth(1).ok_or("No user_id")?)?;
+            users.push((user_id, User::try_from(item)?));
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok(users);
+        }
+    }
+}
+
+/// All of a user's votes, using the `UserVotes` index. Shadow votes share the
+/// index, so they're filtered out.
+pub async fn query_user_votes(
+    user_id: &Uuid,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<UserVote>, Error> {
+    let mut votes = vec![];
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .query()
+            .table_name(&config.table_name)
+            .index_name("UserVotes")
+            .key_condition_expression("UserVotes_PK = :user_id")
+            .filter_expression("entity_type = :entity_type")
+            .expression_attribute_values(":user_id", S(user_id.hyphenated().to_string()))
+            .expression_attribute_values(":entity_type", S("Vote".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            votes.push(UserVote::try_from(item)?);
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok(votes);
+        }
+    }
+}
 
 pub fn get_settings() -> HashMap<String, AttributeValue> {
     HashMap::from([
@@ -27,91 +148,283 @@ pub fn get_user(user_id: &Uuid) -> HashMap<String, AttributeValue> {
     ])
 }
 
-pub fn get_daily_user_history(day: &String, user_id: &Uuid) -> HashMap<String, AttributeValue> {
+pub fn get_vote(vote: &Vote) -> HashMap<String, AttributeValue> {
     HashMap::from([
-        ("PK".to_string(), S(format!("day#{}", day))),
+        ("PK".to_string(), S(format!("link#{}", vote.link.hostname))),
         (
             "SK".to_string(),
-            S(format!("user#{}", user_id.hyphenated())),
+            S(format!("user#{}", vote.user_id.hyphenated())),
         ),
     ])
 }
 
-pub fn get_vote(vote: &Vote) -> HashMap<String, AttributeValue> {
+pub fn get_link_detail(link: &Link) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("PK".to_string(), S(format!("link#{}", link.hostname))),
+        ("SK".to_string(), S(format!("link#{}", link.hostname))),
+    ])
+}
+
+pub fn get_shadow_vote(vote: &Vote) -> HashMap<String, AttributeValue> {
     HashMap::from([
         ("PK".to_string(), S(format!("link#{}", vote.link.hostname))),
         (
             "SK".to_string(),
-            S(format!("user#{}", vote.user_id.hyphenated())),
+            S(format!("shadow#{}", vote.user_id.hyphenated())),
         ),
     ])
 }
 
-pub fn put_new_user(user_id: &Uuid, created_at: &String, config: &Config) -> TransactWriteItem {
-    TransactWriteItem::builder()
+pub fn get_link_override(link: &Link) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("PK".to_string(), S(format!("link#{}", link.hostname))),
+        ("SK".to_string(), S("override".to_string())),
+    ])
+}
+
+pub fn get_link_review(link: &Link, day: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("PK".to_string(), S("review".to_string())),
+        (
+            "SK".to_string(),
+            S(format!("link#{}#{}", link.hostname, day)),
+        ),
+    ])
+}
+
+pub fn get_migration_progress(target_version: u32) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("PK".to_string(), S("migrations".to_string())),
+        ("SK".to_string(), S(format!("version#{}", target_version))),
+    ])
+}
+
+pub fn get_daily_stats(day: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("PK".to_string(), S("stats".to_string())),
+        ("SK".to_string(), S(format!("day#{}", day))),
+    ])
+}
+
+pub fn get_link_stats() -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("PK".to_string(), S("stats".to_string())),
+        ("SK".to_string(), S("links".to_string())),
+    ])
+}
+
+pub fn get_filter_list_page(page: u32) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("PK".to_string(), S("filter-list".to_string())),
+        ("SK".to_string(), S(format!("page#{:04}", page))),
+    ])
+}
+
+/// A user is created by their first vote, which counts towards that day's limit
+pub fn put_new_user(
+    user_id: &Uuid,
+    created_at: &str,
+    reputation: f64,
+    recent_votes_received_at: Vec<String>,
+    config: &Config,
+) -> Result<TransactWriteItem, Error> {
+    let user = User {
+        is_banned: false,
+        is_shadow_banned: false,
+        created_at: created_at.to_string(),
+        reputation,
+        count_of_bans: 0,
+        last_vote_day: created_at[..10].to_string(),
+        count_of_votes_on_last_vote_day: 1,
+        recent_votes_received_at,
+    };
+    let mut item = to_item(&user, "User")?;
+    item.extend(get_user(user_id));
+    Ok(TransactWriteItem::builder()
         .put(
             Put::builder()
-                .item("PK", S(format!("user#{}", user_id.hyphenated())))
-                .item("SK", S(format!("user#{}", user_id.hyphenated())))
-                .item("entity_type", S("User".to_string()))
-                .item("created_at", S(created_at.clone()))
-                .item("is_banned", Bool(false))
+                .set_item(Some(item))
                 .table_name(&config.table_name)
                 .build(),
         )
-        .build()
+        .build())
 }
 
-pub fn put_vote(vote: &Vote, config: &Config) -> TransactWriteItem {
-    TransactWriteItem::builder()
+pub fn put_vote(vote: &Vote, config: &Config) -> Result<TransactWriteItem, Error> {
+    put_vote_item(
+        vote,
+        format!("user#{}", vote.user_id.hyphenated()),
+        "Vote",
+        config,
+    )
+}
+
+/// Votes from shadow banned users are stored next to the real ones, where
+/// nothing that reads `user#` votes will pick them up
+pub fn put_shadow_vote(vote: &Vote, config: &Config) -> Result<TransactWriteItem, Error> {
+    put_vote_item(
+        vote,
+        format!("shadow#{}", vote.user_id.hyphenated()),
+        "ShadowVote",
+        config,
+    )
+}
+
+fn put_vote_item(
+    vote: &Vote,
+    sort_key: String,
+    entity_type: &str,
+    config: &Config,
+) -> Result<TransactWriteItem, Error> {
+    let mut item = to_item(vote, entity_type)?;
+    item.insert("SK".to_string(), S(sort_key));
+    item.insert(
+        "UserVotes_PK".to_string(),
+        S(vote.user_id.hyphenated().to_string()),
+    );
+    Ok(TransactWriteItem::builder()
         .put(
             Put::builder()
-                .item("PK", S(format!("link#{}", vote.link.hostname)))
-                .item("SK", S(format!("user#{}", vote.user_id.hyphenated())))
-                .item("entity_type", S("Vote".to_string()))
-                .item("value", N(vote.value.to_string()))
-                .item("created_at", S(vote.created_at.clone()))
-                .item("UserVotes_PK", S(vote.user_id.hyphenated().to_string()))
+                .set_item(Some(item))
                 .table_name(&config.table_name)
                 .build(),
         )
-        .build()
+        .build())
 }
 
-pub fn update_link_detail(link: &Link, vote_value: i32, config: &Config) -> TransactWriteItem {
-    TransactWriteItem::builder()
-        .update(
-            Update::builder()
-                .key("PK", S(format!("link#{}", link.hostname)))
-                .key("SK", S(format!("link#{}", link.hostname)))
-                .update_expression(format!(
-                    "SET {},{},{}",
-                    "count_of_votes = if_not_exists(count_of_votes, :zero) + :one",
-                    "sum_of_votes = if_not_exists(sum_of_votes, :zero) + :value",
-                    "entity_type = :entity_type",
-                ))
-                .expression_attribute_values(":value", N(vote_value.to_string()))
-                .expression_attribute_values(":zero", N(0.to_string()))
-                .expression_attribute_values(":one", N(1.to_string()))
-                .expression_attribute_values(":entity_type", S("LinkDetail".to_string()))
-                .table_name(&config.table_name)
-                .build(),
+// Weighted sums are kept to the same 2 decimal places as reputations
+fn weighted_number(value: f64) -> AttributeValue {
+    N(format!("{:.2}", value))
+}
+
+/// `sum_of_votes` is the link's current unweighted sum, used as the starting
+/// point for `weighted_sum_of_votes` on links from before reputations existed.
+/// It's `None` if this is the link's first vote.
+pub fn update_link_detail(
+    vote: &Vote,
+    sum_of_votes: Option<i32>,
+    config: &Config,
+) -> TransactWriteItem {
+    let mut set_expressions = vec![
+        "count_of_votes = if_not_exists(count_of_votes, :zero) + :one".to_string(),
+        "sum_of_votes = if_not_exists(sum_of_votes, :zero) + :value".to_string(),
+        "weighted_sum_of_votes = if_not_exists(weighted_sum_of_votes, :sum) + :weighted_value"
+            .to_string(),
+        "entity_type = :entity_type".to_string(),
+        "schema_version = if_not_exists(schema_version, :schema_version)".to_string(),
+    ];
+    // Links from before this was recorded are left without one
+    if sum_of_votes.is_none() {
+        set_expressions.push("created_at = :created_at".to_string());
+    }
+    if let Some(reason) = vote.reason {
+        set_expressions.push(format!(
+            "{0} = if_not_exists({0}, :zero) + :one",
+            reason.count_attribute()
+        ));
+    }
+    if let Some(dimension) = vote.dimension {
+        set_expressions.push(format!(
+            "{0} = if_not_exists({0}, :zero) + :one",
+            dimension.count_attribute()
+        ));
+        set_expressions.push(format!(
+            "{0} = if_not_exists({0}, :zero) + :value",
+            dimension.sum_attribute()
+        ));
+    }
+    let mut update = Update::builder()
+        .key("PK", S(format!("link#{}", vote.link.hostname)))
+        .key("SK", S(format!("link#{}", vote.link.hostname)))
+        .update_expression(format!("SET {}", set_expressions.join(",")))
+        .expression_attribute_values(":value", N(vote.value.to_string()))
+        .expression_attribute_values(":sum", N(sum_of_votes.unwrap_or(0).to_string()))
+        .expression_attribute_values(
+            ":weighted_value",
+            weighted_number(vote.value as f64 * vote.weight),
         )
+        .expression_attribute_values(":zero", N(0.to_string()))
+        .expression_attribute_values(":one", N(1.to_string()))
+        .expression_attribute_values(":entity_type", S("LinkDetail".to_string()))
+        .expression_attribute_values(":schema_version", N(SCHEMA_VERSION.to_string()));
+    if sum_of_votes.is_none() {
+        update = update.expression_attribute_values(":created_at", S(vote.created_at.clone()));
+    }
+    TransactWriteItem::builder()
+        .update(update.table_name(&config.table_name).build())
         .build()
 }
 
 pub fn update_existing_link_detail(
-    link: &Link,
-    vote_value_change: i32,
+    old_vote: &Vote,
+    vote: &Vote,
+    sum_of_votes: i32,
     config: &Config,
 ) -> TransactWriteItem {
+    let vote_value_change = vote.value - old_vote.value;
+    let weighted_change = vote.value as f64 * vote.weight - old_vote.value as f64 * old_vote.weight;
+    let mut set_expressions = vec![
+        "sum_of_votes = sum_of_votes + :change".to_string(),
+        "weighted_sum_of_votes = if_not_exists(weighted_sum_of_votes, :sum) + :weighted_change"
+            .to_string(),
+    ];
+    let mut values = HashMap::from([
+        (":change".to_string(), N(vote_value_change.to_string())),
+        (":sum".to_string(), N(sum_of_votes.to_string())),
+        (
+            ":weighted_change".to_string(),
+            weighted_number(weighted_change),
+        ),
+    ]);
+    // Move the vote from the old reason's count to the new one
+    if old_vote.reason != vote.reason {
+        if let Some(old_reason) = old_vote.reason {
+            set_expressions.push(format!("{0} = {0} - :one", old_reason.count_attribute()));
+        }
+        if let Some(reason) = vote.reason {
+            set_expressions.push(format!(
+                "{0} = if_not_exists({0}, :zero) + :one",
+                reason.count_attribute()
+            ));
+            values.insert(":zero".to_string(), N(0.to_string()));
+        }
+        values.insert(":one".to_string(), N(1.to_string()));
+    }
+    // Same for the dimension aggregates, which also carry the vote's value
+    if old_vote.dimension == vote.dimension {
+        if let Some(dimension) = vote.dimension {
+            set_expressions.push(format!("{0} = {0} + :change", dimension.sum_attribute()));
+        }
+    } else {
+        if let Some(old_dimension) = old_vote.dimension {
+            set_expressions.push(format!("{0} = {0} - :one", old_dimension.count_attribute()));
+            set_expressions.push(format!(
+                "{0} = {0} - :old_value",
+                old_dimension.sum_attribute()
+            ));
+            values.insert(":old_value".to_string(), N(old_vote.value.to_string()));
+        }
+        if let Some(dimension) = vote.dimension {
+            set_expressions.push(format!(
+                "{0} = if_not_exists({0}, :zero) + :one",
+                dimension.count_attribute()
+            ));
+            set_expressions.push(format!(
+                "{0} = if_not_exists({0}, :zero) + :value",
+                dimension.sum_attribute()
+            ));
+            values.insert(":zero".to_string(), N(0.to_string()));
+            values.insert(":value".to_string(), N(vote.value.to_string()));
+        }
+        values.insert(":one".to_string(), N(1.to_string()));
+    }
     TransactWriteItem::builder()
         .update(
             Update::builder()
-                .key("PK", S(format!("link#{}", link.hostname)))
-                .key("SK", S(format!("link#{}", link.hostname)))
-                .update_expression(format!("SET {}", "sum_of_votes = sum_of_votes + :change",))
-                .expression_attribute_values(":change", N(vote_value_change.to_string()))
+                .key("PK", S(format!("link#{}", vote.link.hostname)))
+                .key("SK", S(format!("link#{}", vote.link.hostname)))
+                .update_expression(format!("SET {}", set_expressions.join(",")))
+                .set_expression_attribute_values(Some(values))
                 .table_name(&config.table_name)
                 .build(),
         )
@@ -125,16 +438,18 @@ pub fn increment_link_history(day: &String, vote: &Vote, config: &Config) -> Tra
                 .key("PK", S(format!("day#{}", day)))
                 .key("SK", S(format!("link#{}", vote.link.hostname)))
                 .update_expression(format!(
-                    "SET {},{},{},{}",
+                    "SET {},{},{},{},{}",
                     "count_of_votes = if_not_exists(count_of_votes, :zero) + :one",
                     "sum_of_votes = if_not_exists(sum_of_votes, :zero) + :value",
                     "entity_type = :entity_type",
                     "DailyLinkHistory_PK = :DailyLinkHistory_PK",
+                    "schema_version = if_not_exists(schema_version, :schema_version)",
                 ))
                 .expression_attribute_values(":value", N(vote.value.to_string()))
                 .expression_attribute_values(":zero", N(0.to_string()))
                 .expression_attribute_values(":one", N(1.to_string()))
                 .expression_attribute_values(":entity_type", S("LinkHistory".to_string()))
+                .expression_attribute_values(":schema_version", N(SCHEMA_VERSION.to_string()))
                 .expression_attribute_values(":DailyLinkHistory_PK", S(format!("day#{}", day)))
                 .table_name(&config.table_name)
                 .build(),
@@ -149,16 +464,18 @@ pub fn increment_user_history(day: &String, vote: &Vote, config: &Config) -> Tra
                 .key("PK", S(format!("day#{}", day)))
                 .key("SK", S(format!("user#{}", vote.user_id.hyphenated())))
                 .update_expression(format!(
-                    "SET {},{},{},{}",
+                    "SET {},{},{},{},{}",
                     "count_of_votes = if_not_exists(count_of_votes, :zero) + :one",
                     "sum_of_votes = if_not_exists(sum_of_votes, :zero) + :value",
                     "entity_type = :entity_type",
                     "DailyUserHistory_PK = :DailyUserHistory_PK",
+                    "schema_version = if_not_exists(schema_version, :schema_version)",
                 ))
                 .expression_attribute_values(":value", N(vote.value.to_string()))
                 .expression_attribute_values(":zero", N(0.to_string()))
                 .expression_attribute_values(":one", N(1.to_string()))
                 .expression_attribute_values(":entity_type", S("UserHistory".to_string()))
+                .expression_attribute_values(":schema_version", N(SCHEMA_VERSION.to_string()))
                 .expression_attribute_values(":DailyUserHistory_PK", S(format!("day#{}", day)))
                 .table_name(&config.table_name)
                 .build(),
@@ -245,3 +562,215 @@ pub fn update_user_history(
         )
         .build()
 }
+
+pub fn put_link_review(
+    vote_burst: &VoteBurst,
+    is_frozen: bool,
+    created_at: &str,
+    config: &Config,
+) -> TransactWriteItem {
+    TransactWriteItem::builder()
+        .put(
+            Put::builder()
+                .set_item(Some(get_link_review(&vote_burst.link, &vote_burst.day)))
+                .item("entity_type", S("LinkReview".to_string()))
+                .item("schema_version", N(SCHEMA_VERSION.to_string()))
+                .item("link", S(vote_burst.link.hostname.clone()))
+                .item("day", S(vote_burst.day.clone()))
+                .item("count_of_votes", N(vote_burst.count_of_votes.to_string()))
+                .item("sum_of_votes", N(vote_burst.sum_of_votes.to_string()))
+                .item(
+                    "baseline_count_of_votes",
+                    weighted_number(vote_burst.baseline_count_of_votes),
+                )
+                .item(
+                    "share_of_new_users",
+                    weighted_number(vote_burst.share_of_new_users),
+                )
+                .item("reasons", Ss(vote_burst.reasons.clone()))
+                .item("is_frozen", Bool(is_frozen))
+                .item("created_at", S(created_at.to_string()))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build()
+}
+
+/// Hold the link at its current score until someone has reviewed it. An existing
+/// freeze is kept so a second burst can't move it either.
+pub fn freeze_link_score(link: &Link, score: &Score, config: &Config) -> TransactWriteItem {
+    TransactWriteItem::builder()
+        .update(
+            Update::builder()
+                .set_key(Some(get_link_detail(link)))
+                .update_expression("SET frozen_score = if_not_exists(frozen_score, :score)")
+                .expression_attribute_values(":score", S(score.as_str().to_string()))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build()
+}
+
+pub fn delete_link_review(link: &Link, day: &str, config: &Config) -> TransactWriteItem {
+    TransactWriteItem::builder()
+        .delete(
+            Delete::builder()
+                .set_key(Some(get_link_review(link, day)))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build()
+}
+
+/// Go back to the link's calculated score. The link has to exist, otherwise the
+/// update would create an item with only a key.
+pub fn unfreeze_link_score(link: &Link, config: &Config) -> TransactWriteItem {
+    TransactWriteItem::builder()
+        .update(
+            Update::builder()
+                .set_key(Some(get_link_detail(link)))
+                .update_expression("REMOVE frozen_score")
+                .condition_expression("attribute_exists(PK)")
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build()
+}
+
+/// Sets the count of first votes the user has made on `day`, by the server's clock,
+/// and when their latest votes were received
+pub fn update_user_daily_votes(
+    user_id: &Uuid,
+    day: &str,
+    count_of_votes: u32,
+    recent_votes_received_at: &[String],
+    config: &Config,
+) -> TransactWriteItem {
+    TransactWriteItem::builder()
+        .update(
+            Update::builder()
+                .set_key(Some(get_user(user_id)))
+                .update_expression(format!(
+                    "SET {},{},{}",
+                    "last_vote_day = :day",
+                    "count_of_votes_on_last_vote_day = :count",
+                    "recent_votes_received_at = :recent_votes_received_at",
+                ))
+                .expression_attribute_values(":day", S(day.to_string()))
+                .expression_attribute_values(":count", N(count_of_votes.to_string()))
+                .expression_attribute_values(
+                    ":recent_votes_received_at",
+                    L(recent_votes_received_at
+                        .iter()
+                        .map(|received_at| S(received_at.clone()))
+                        .collect()),
+                )
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build()
+}
+
+/// Ban a user for breaking one of the ban rules, recording which and why
+pub fn ban_user(
+    user_id: &Uuid,
+    decision: &BanDecision,
+    banned_at: &str,
+    config: &Config,
+) -> TransactWriteItem {
+    let update_expression = match decision.action {
+        BanAction::Ban => {
+            "SET is_banned = :true, ban_reason = :reason, banned_at = :banned_at ADD count_of_bans :one"
+        }
+        BanAction::ShadowBan => {
+            "SET is_shadow_banned = :true, ban_reason = :reason, banned_at = :banned_at"
+        }
+    };
+    let mut update = Update::builder()
+        .set_key(Some(get_user(user_id)))
+        .update_expression(update_expression)
+        .expression_attribute_values(":true", Bool(true))
+        .expression_attribute_values(
+            ":reason",
+            S(format!("{}: {}", decision.rule, decision.reason)),
+        )
+        .expression_attribute_values(":banned_at", S(banned_at.to_string()));
+    if decision.action == BanAction::Ban {
+        update = update.expression_attribute_values(":one", N(1.to_string()));
+    }
+    TransactWriteItem::builder()
+        .update(update.table_name(&config.table_name).build())
+        .build()
+}
+
+pub fn put_migration_progress(
+    progress: &MigrationProgress,
+    config: &Config,
+) -> Result<TransactWriteItem, Error> {
+    let mut item = to_item(progress, "MigrationProgress")?;
+    item.extend(get_migration_progress(progress.target_version));
+    Ok(TransactWriteItem::builder()
+        .put(
+            Put::builder()
+                .set_item(Some(item))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build())
+}
+
+pub fn put_daily_stats(
+    daily_stats: &DailyStats,
+    config: &Config,
+) -> Result<TransactWriteItem, Error> {
+    let mut item = to_item(daily_stats, "DailyStats")?;
+    item.extend(get_daily_stats(&daily_stats.day));
+    Ok(TransactWriteItem::builder()
+        .put(
+            Put::builder()
+                .set_item(Some(item))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build())
+}
+
+pub fn put_link_stats(link_stats: &LinkStats, config: &Config) -> Result<TransactWriteItem, Error> {
+    let mut item = to_item(link_stats, "LinkStats")?;
+    item.extend(get_link_stats());
+    Ok(TransactWriteItem::builder()
+        .put(
+            Put::builder()
+                .set_item(Some(item))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build())
+}
+
+pub fn put_filter_list_page(
+    filter_list_page: &FilterListPage,
+    config: &Config,
+) -> Result<TransactWriteItem, Error> {
+    let mut item = to_item(filter_list_page, "FilterListPage")?;
+    item.extend(get_filter_list_page(filter_list_page.page));
+    Ok(TransactWriteItem::builder()
+        .put(
+            Put::builder()
+                .set_item(Some(item))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build())
+}
+
+pub fn delete_filter_list_page(page: u32, config: &Config) -> TransactWriteItem {
+    TransactWriteItem::builder()
+        .delete(
+            Delete::builder()
+                .set_key(Some(get_filter_list_page(page)))
+                .table_name(&config.table_name)
+                .build(),
+        )
+        .build()
+}
@@ -0,0 +1,314 @@
+use brotli::CompressorWriter;
+use flate2::{write::GzEncoder, Compression as GzipLevel};
+use lambda_http::{
+    http::{
+        header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY},
+        HeaderMap,
+    },
+    Body, Error, Response,
+};
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    Json,
+    Cbor,
+    MessagePack,
+}
+impl Encoding {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Cbor => "application/cbor",
+            Encoding::MessagePack => "application/msgpack",
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Encoding::Json),
+            "application/cbor" => Some(Encoding::Cbor),
+            "application/msgpack" | "application/x-msgpack" => Some(Encoding::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    Identity,
+    Gzip,
+    Brotli,
+}
+impl Compression {
+    fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::Identity => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Brotli => Some("br"),
+        }
+    }
+
+    fn from_coding(coding: &str) -> Option<Self> {
+        match coding {
+            "identity" => Some(Compression::Identity),
+            "gzip" | "x-gzip" => Some(Compression::Gzip),
+            "br" => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+}
+
+/// Pick the response encoding from the `Accept` header, falling back to JSON
+pub fn negotiate_encoding(headers: &HeaderMap) -> Encoding {
+    preferred(headers.get_all(ACCEPT).iter(), Encoding::from_media_type).unwrap_or(Encoding::Json)
+}
+
+/// Pick the compression from the `Accept-Encoding` header. Only JSON is
+/// compressed, the binary encodings are already compact.
+pub fn negotiate_compression(headers: &HeaderMap, encoding: Encoding) -> Compression {
+    if encoding != Encoding::Json {
+        return Compression::Identity;
+    }
+    preferred(
+        headers.get_all(ACCEPT_ENCODING).iter(),
+        Compression::from_coding,
+    )
+    .unwrap_or(Compression::Identity)
+}
+
+// Parse a header like `application/cbor;q=0.9, application/json;q=0.5` and
+// return the supported value with the highest quality. Ties go to whichever
+// was listed first.
+fn preferred<'a, T>(
+    header_values: impl Iterator<Item = &'a lambda_http::http::HeaderValue>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Option<T> {
+    let mut best: Option<(T, f32)> = None;
+    for value in header_values.filter_map(|value| value.to_str().ok()) {
+        for entry in value.split(',') {
+            let mut parameters = entry.split(';').map(str::trim);
+            let name = parameters.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = parameters
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                continue;
+            }
+            if let Some(parsed) = parse(&name) {
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best_quality)| quality > *best_quality)
+                {
+                    best = Some((parsed, quality));
+                }
+            }
+        }
+    }
+    best.map(|(parsed, _)| parsed)
+}
+
+pub fn encode<T: Serialize>(value: &T, encoding: Encoding) -> Result<Vec<u8>, Error> {
+    match encoding {
+        Encoding::Json => Ok(serde_json::to_vec(value)?),
+        Encoding::Cbor => {
+            let mut bytes = vec![];
+            ciborium::ser::into_writer(value, &mut bytes)?;
+            Ok(bytes)
+        }
+        // Structs are written as maps, as fields that are left out would shift an array
+        Encoding::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+    }
+}
+
+pub fn compress(bytes: Vec<u8>, compression: Compression) -> Result<Vec<u8>, Error> {
+    match compression {
+        Compression::Identity => Ok(bytes),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(vec![], GzipLevel::default());
+            encoder.write_all(&bytes)?;
+            Ok(encoder.finish()?)
+        }
+        Compression::Brotli => {
+            let mut compressed = vec![];
+            {
+                // Quality 5 is a good trade off between speed and size for small payloads
+                let mut writer = CompressorWriter::new(&mut compressed, 4096, 5, 22);
+                writer.write_all(&bytes)?;
+            }
+            Ok(compressed)
+        }
+    }
+}
+
+/// Serialize `value` into whichever format the client asked for. JSON is only
+/// compressed here if `compress_responses` is set, behind API Gateway it does that itself.
+pub fn negotiated_response<T: Serialize>(
+    value: &T,
+    request_headers: &HeaderMap,
+    compress_responses: bool,
+) -> Result<Response<Body>, Error> {
+    let encoding = negotiate_encoding(request_headers);
+    let compression = match compress_responses {
+        true => negotiate_compression(request_headers, encoding),
+        false => Compression::Identity,
+    };
+    let bytes = compress(encode(value, encoding)?, compression)?;
+
+    let mut response = Response::builder()
+        .header(CONTENT_TYPE, encoding.content_type())
+        .header(VARY, "Accept, Accept-Encoding");
+    if let Some(content_encoding) = compression.content_encoding() {
+        response = response.header(CONTENT_ENCODING, content_encoding);
+    }
+    let body = match (encoding, compression) {
+        (Encoding::Json, Compression::Identity) => Body::Text(String::from_utf8(bytes)?),
+        _ => Body::Binary(bytes),
+    };
+    Ok(response.body(body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Link, LinkScore, Score};
+    use brotli::Decompressor;
+    use flate2::read::GzDecoder;
+    use lambda_http::http::HeaderValue;
+    use std::io::Read;
+
+    fn link_scores() -> Vec<LinkScore> {
+        vec![
+            LinkScore::new(Link::new("www.google.com"), Score::Good),
+            LinkScore::new(Link::new("abc.com"), Score::Bad),
+            LinkScore::new(Link::new("domain.me"), Score::Controversial),
+            LinkScore::new(Link::new("a.b.c.d.com"), Score::NoScore),
+        ]
+    }
+
+    fn header_map(name: lambda_http::http::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        assert_eq!(negotiate_encoding(&HeaderMap::new()), Encoding::Json);
+        for (accept, expected) in [
+            ("application/json", Encoding::Json),
+            ("*/*", Encoding::Json),
+            ("text/html", Encoding::Json),
+            ("application/cbor", Encoding::Cbor),
+            ("application/msgpack", Encoding::MessagePack),
+            ("application/x-msgpack", Encoding::MessagePack),
+            ("application/json, application/cbor", Encoding::Json),
+            ("application/json;q=0.5, application/cbor", Encoding::Cbor),
+            (
+                "application/cbor;q=0, application/msgpack;q=0.1",
+                Encoding::MessagePack,
+            ),
+            ("Application/CBOR", Encoding::Cbor),
+        ] {
+            assert_eq!(negotiate_encoding(&header_map(ACCEPT, accept)), expected);
+        }
+    }
+
+    #[test]
+    fn test_negotiate_compression() {
+        for (accept_encoding, expected) in [
+            ("", Compression::Identity),
+            ("deflate", Compression::Identity),
+            ("gzip", Compression::Gzip),
+            ("gzip, deflate, br", Compression::Gzip),
+            ("br;q=1.0, gzip;q=0.8", Compression::Brotli),
+            ("gzip;q=0, br;q=0", Compression::Identity),
+        ] {
+            let headers = header_map(ACCEPT_ENCODING, accept_encoding);
+            assert_eq!(negotiate_compression(&headers, Encoding::Json), expected);
+        }
+
+        // Binary encodings are never compressed
+        let headers = header_map(ACCEPT_ENCODING, "gzip, br");
+        assert_eq!(
+            negotiate_compression(&headers, Encoding::Cbor),
+            Compression::Identity
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let link_scores = link_scores();
+
+        let json = encode(&link_scores, Encoding::Json).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Vec<LinkScore>>(&json).unwrap(),
+            link_scores
+        );
+        // Empty reasons and dimensions, and unmoderated scores, add nothing
+        assert!(json.starts_with(br#"[{"link":{"hostname":"www.google.com"},"score":"Good"},"#));
+
+        let cbor = encode(&link_scores, Encoding::Cbor).unwrap();
+        assert_eq!(
+            ciborium::de::from_reader::<Vec<LinkScore>, _>(cbor.as_slice()).unwrap(),
+            link_scores
+        );
+
+        let msgpack = encode(&link_scores, Encoding::MessagePack).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<Vec<LinkScore>>(&msgpack).unwrap(),
+            link_scores
+        );
+        assert!(msgpack.len() < json.len());
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let json = encode(&vec![link_scores(); 20], Encoding::Json).unwrap();
+
+        let gzipped = compress(json.clone(), Compression::Gzip).unwrap();
+        let mut decompressed = vec![];
+        GzDecoder::new(gzipped.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, json);
+        assert!(gzipped.len() < json.len());
+
+        let brotlied = compress(json.clone(), Compression::Brotli).unwrap();
+        let mut decompressed = vec![];
+        Decompressor::new(brotlied.as_slice(), 4096)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, json);
+        assert!(brotlied.len() < json.len());
+    }
+
+    #[test]
+    fn test_negotiated_response() {
+        let response = negotiated_response(&link_scores(), &HeaderMap::new(), true).unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        match response.body() {
+            // Links without any reasons leave them out
+            Body::Text(body) => assert!(!body.contains("reasons")),
+            _ => panic!("Expected a text body"),
+        }
+
+        let headers = header_map(ACCEPT, "application/cbor");
+        let response = negotiated_response(&link_scores(), &headers, true).unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/cbor");
+        assert!(matches!(response.body(), Body::Binary(_)));
+
+        let headers = header_map(ACCEPT_ENCODING, "br");
+        let response = negotiated_response(&link_scores(), &headers, true).unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+        assert!(matches!(response.body(), Body::Binary(_)));
+
+        let response = negotiated_response(&link_scores(), &headers, false).unwrap();
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert!(matches!(response.body(), Body::Text(_)));
+    }
+}
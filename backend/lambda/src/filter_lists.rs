@@ -0,0 +1,322 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    dynamodb::{delete_filter_list_page, put_filter_list_page},
+    scoring::calculate_link_scores,
+    stats::update_link_stats,
+    types::{
+        database::{FilterListPage, LinkDetail, LinkOverride},
+        Config, Link, Score,
+    },
+};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, AttributeValue::*},
+    Client,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use lambda_http::Error;
+
+const TITLE: &str = "Discontent bad sites";
+const HOMEPAGE: &str = "https://github.com/tom-barone/Discontent";
+// Hostnames per `FilterListPage`, well under DynamoDB's 400 KB item limit
+const PAGE_SIZE: usize = 1000;
+// Every page is written in one transaction, which is limited to 100 items
+const MAXIMUM_PAGES: usize = 100;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FilterListFormat {
+    /// `*://example.com/*` match patterns
+    UBlacklist,
+    /// `0.0.0.0 example.com`
+    Hosts,
+    /// Adblock Plus / uBlock Origin `||example.com^` filters
+    Adblock,
+}
+impl FilterListFormat {
+    pub const ALL: [FilterListFormat; 3] = [
+        FilterListFormat::UBlacklist,
+        FilterListFormat::Hosts,
+        FilterListFormat::Adblock,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterListFormat::UBlacklist => "ublacklist",
+            FilterListFormat::Hosts => "hosts",
+            FilterListFormat::Adblock => "adblock",
+        }
+    }
+}
+impl FromStr for FilterListFormat {
+    type Err = String;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        FilterListFormat::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == format)
+            .ok_or(format!("Unknown filter list format {}", format))
+    }
+}
+
+/// Every link in the table along with any overrides, for scoring all of them at once
+pub async fn scan_link_details(
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<(HashMap<Link, LinkDetail>, HashMap<Link, LinkOverride>), Error> {
+    let mut link_details = HashMap::new();
+    let mut link_overrides = HashMap::new();
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .scan()
+            .table_name(&config.table_name)
+            .filter_expression("entity_type IN (:link_detail, :link_override)")
+            .expression_attribute_values(":link_detail", S("LinkDetail".to_string()))
+            .expression_attribute_values(":link_override", S("LinkOverride".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            let entity_type = item
+                .get("entity_type")
+                .ok_or("No entity_type")?
+                .as_s()
+                .or(Err("entity_type is not a string"))?;
+            if entity_type == "LinkDetail" {
+                let link_detail = LinkDetail::try_from(item)?;
+                link_details.insert(link_detail.link.clone(), link_detail);
+            } else {
+                let link_override = LinkOverride::try_from(item)?;
+                link_overrides.insert(link_override.link.clone(), link_override);
+            }
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok((link_details, link_overrides));
+        }
+    }
+}
+
+/// Links that score `Bad`, sorted by hostname
+pub fn bad_links(
+    link_details: &HashMap<Link, LinkDetail>,
+    link_overrides: &HashMap<Link, LinkOverride>,
+    use_weighted_scores: bool,
+    now: DateTime<Utc>,
+) -> Vec<Link> {
+    let mut links: Vec<Link> = link_details
+        .keys()
+        .chain(link_overrides.keys())
+        .cloned()
+        .collect();
+    links.sort();
+    links.dedup();
+    calculate_link_scores(
+        &links,
+        link_details,
+        link_overrides,
+        &[],
+        use_weighted_scores,
+        &now.to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+    .into_iter()
+    .filter(|link_score| *link_score.score() == Score::Bad)
+    .map(|link_score| link_score.link().clone())
+    .collect()
+}
+
+/// Scores every link and replaces the stored filter list with the `Bad` ones,
+/// and updates the link counts for `GET /v1/stats` from the same scan. Run
+/// daily by `compute-filter-lists`, so the scan isn't in the request path.
+pub async fn update_filter_list(
+    now: DateTime<Utc>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<Link>, Error> {
+    let (link_details, link_overrides) = scan_link_details(config, dynamo_db_client).await?;
+    let links = bad_links(
+        &link_details,
+        &link_overrides,
+        config.use_weighted_scores,
+        now,
+    );
+    let pages = filter_list_pages(&links, &now.to_rfc3339_opts(SecondsFormat::Secs, true));
+    if pages.len() > MAXIMUM_PAGES {
+        return Err(format!("Too many bad links for one filter list [{}]", links.len()).into());
+    }
+
+    // A shorter list than last time leaves pages behind
+    let count_of_old_pages = query_filter_list_pages(config, dynamo_db_client)
+        .await?
+        .len();
+    let mut write_requests = pages
+        .iter()
+        .map(|page| put_filter_list_page(page, config))
+        .collect::<Result<Vec<_>, _>>()?;
+    for page in pages.len()..count_of_old_pages {
+        write_requests.push(delete_filter_list_page(page as u32, config));
+    }
+    dynamo_db_client
+        .transact_write_items()
+        .set_transact_items(Some(write_requests))
+        .send()
+        .await?;
+
+    update_link_stats(
+        &link_details,
+        &link_overrides,
+        now,
+        config,
+        dynamo_db_client,
+    )
+    .await?;
+    Ok(links)
+}
+
+/// The links from the last `update_filter_list`, and when they were scored
+pub async fn get_filter_list(
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<(Vec<Link>, DateTime<Utc>), Error> {
+    let pages = query_filter_list_pages(config, dynamo_db_client).await?;
+    let generated_at = pages
+        .first()
+        .ok_or("The filter list hasn't been generated yet")?
+        .generated_at
+        .clone();
+    let links = pages
+        .into_iter()
+        .flat_map(|page| page.hostnames)
+        .map(|hostname| Link::new(&hostname))
+        .collect();
+    Ok((
+        links,
+        DateTime::parse_from_rfc3339(&generated_at)?.with_timezone(&Utc),
+    ))
+}
+
+// In page order, which is the order of the links
+async fn query_filter_list_pages(
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<FilterListPage>, Error> {
+    let mut pages = vec![];
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .query()
+            .table_name(&config.table_name)
+            .key_condition_expression("PK = :filter_list")
+            .expression_attribute_values(":filter_list", S("filter-list".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            pages.push(FilterListPage::try_from(item)?);
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok(pages);
+        }
+    }
+}
+
+/// Splits the links into pages. There's always at least one, so an empty list
+/// can be told apart from one that hasn't been generated.
+pub fn filter_list_pages(links: &[Link], generated_at: &str) -> Vec<FilterListPage> {
+    let hostnames: Vec<String> = links.iter().map(|link| link.hostname.clone()).collect();
+    let mut chunks: Vec<&[String]> = hostnames.chunks(PAGE_SIZE).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(page, hostnames)| FilterListPage {
+            page: page as u32,
+            hostnames: hostnames.to_vec(),
+            generated_at: generated_at.to_string(),
+        })
+        .collect()
+}
+
+/// The list with a header, versioned by when it was generated
+pub fn render_filter_list(links: &[Link], format: FilterListFormat, now: DateTime<Utc>) -> String {
+    let version = now.format("%Y%m%d%H%M").to_string();
+    let last_modified = now.format("%d %b %Y %H:%M UTC").to_string();
+    let comment = match format {
+        FilterListFormat::Adblock => "!",
+        FilterListFormat::UBlacklist | FilterListFormat::Hosts => "#",
+    };
+
+    let mut lines = vec![];
+    if format == FilterListFormat::Adblock {
+        lines.push("[Adblock Plus 2.0]".to_string());
+    }
+    lines.push(format!("{} Title: {}", comment, TITLE));
+    lines.push(format!("{} Version: {}", comment, version));
+    lines.push(format!("{} Last modified: {}", comment, last_modified));
+    lines.push(format!("{} Expires: 1 day", comment));
+    lines.push(format!("{} Homepage: {}", comment, HOMEPAGE));
+    lines.push(format!("{} Number of entries: {}", comment, links.len()));
+    for link in links {
+        lines.push(match format {
+            FilterListFormat::UBlacklist => format!("*://{}/*", link.hostname),
+            FilterListFormat::Hosts => format!("0.0.0.0 {}", link.hostname),
+            FilterListFormat::Adblock => format!("||{}^", link.hostname),
+        });
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_list_pages() {
+        let pages = filter_list_pages(&[], "2023-02-08T12:30:00Z");
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].hostnames.is_empty());
+
+        let links: Vec<Link> = (0..2500)
+            .map(|index| Link::new(&format!("bad{}.com", index)))
+            .collect();
+        let pages = filter_list_pages(&links, "2023-02-08T12:30:00Z");
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[2].page, 2);
+        assert_eq!(pages[2].hostnames.len(), 500);
+        assert_eq!(pages[1].hostnames[0], "bad1000.com");
+    }
+
+    #[test]
+    fn test_render_filter_list() {
+        let links = vec![Link::new("bad.com"), Link::new("www.spam.net")];
+        let now = DateTime::parse_from_rfc3339("2023-02-08T12:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let header = |comment: &str| {
+            format!(
+                "{0} Title: Discontent bad sites\n\
+                 {0} Version: 202302081230\n\
+                 {0} Last modified: 08 Feb 2023 12:30 UTC\n\
+                 {0} Expires: 1 day\n\
+                 {0} Homepage: https://github.com/tom-barone/Discontent\n\
+                 {0} Number of entries: 2\n",
+                comment
+            )
+        };
+
+        assert_eq!(
+            render_filter_list(&links, FilterListFormat::UBlacklist, now),
+            header("#") + "*://bad.com/*\n*://www.spam.net/*\n"
+        );
+        assert_eq!(
+            render_filter_list(&links, FilterListFormat::Hosts, now),
+            header("#") + "0.0.0.0 bad.com\n0.0.0.0 www.spam.net\n"
+        );
+        assert_eq!(
+            render_filter_list(&links, FilterListFormat::Adblock, now),
+            "[Adblock Plus 2.0]\n".to_string() + &header("!") + "||bad.com^\n||www.spam.net^\n"
+        );
+    }
+}
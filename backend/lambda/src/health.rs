@@ -0,0 +1,117 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    clock::Clock,
+    dynamodb::get_settings,
+    types::{
+        api::{Check, ConfigSummary, Health, Readiness},
+        database::Settings,
+        Config,
+    },
+};
+use aws_sdk_dynamodb::{model::AttributeValue, Client};
+use tracing::*;
+
+// Load balancers give up quickly, so a slow table counts as not ready
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What's running, without touching the database
+pub fn health(clock: &dyn Clock, config: &Config) -> Health {
+    Health {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        config: ConfigSummary {
+            table_name: config.table_name.clone(),
+            use_weighted_scores: config.use_weighted_scores,
+            cors_allowed_origins: config.cors_allowed_origins.clone(),
+            admin_routes_are_enabled: config.admin_key.is_some(),
+            use_local_database: config.use_local_database,
+            randomize_scores: config.randomize_scores,
+            clock_is_fake: clock.fake().is_some(),
+        },
+    }
+}
+
+/// Checks that the table can be reached and that the settings every vote
+/// needs are there and can be read
+pub async fn readiness(config: &Config, dynamo_db_client: &Client) -> Readiness {
+    let request = dynamo_db_client
+        .get_item()
+        .table_name(&config.table_name)
+        .set_key(Some(get_settings()))
+        .send();
+    let checks = match tokio::time::timeout(READINESS_TIMEOUT, request).await {
+        Ok(Ok(response)) => vec![check("table", Ok(())), check_settings(response.item())],
+        Ok(Err(e)) => vec![
+            check("table", Err(e.to_string())),
+            check("settings", Err("The table can't be reached".to_string())),
+        ],
+        Err(_) => vec![
+            check(
+                "table",
+                Err(format!(
+                    "No response within {} seconds",
+                    READINESS_TIMEOUT.as_secs()
+                )),
+            ),
+            check("settings", Err("The table can't be reached".to_string())),
+        ],
+    };
+    let ready = checks.iter().all(|check| check.ok);
+    if !ready {
+        warn!("Not ready [checks={:?}]", checks);
+    }
+    Readiness { ready, checks }
+}
+
+fn check_settings(item: Option<&HashMap<String, AttributeValue>>) -> Check {
+    let result = match item {
+        Some(item) => Settings::try_from(item)
+            .map(|_| ())
+            .map_err(|e| format!("The settings can't be read: {}", e)),
+        None => Err("There are no settings".to_string()),
+    };
+    check("settings", result)
+}
+
+fn check(name: &str, result: Result<(), String>) -> Check {
+    Check {
+        name: name.to_string(),
+        ok: result.is_ok(),
+        error: result.err(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::database::to_item;
+
+    #[test]
+    fn test_check_settings() {
+        assert_eq!(
+            check_settings(None),
+            Check {
+                name: "settings".to_string(),
+                ok: false,
+                error: Some("There are no settings".to_string()),
+            }
+        );
+
+        let settings = Settings {
+            voting_is_disabled: false,
+            maximum_votes_per_user_per_day: 10,
+            freeze_suspicious_links: false,
+            ban_rules: vec![],
+        };
+        let mut item = to_item(&settings, "Settings").unwrap();
+        assert!(check_settings(Some(&item)).ok);
+
+        item.remove("maximum_votes_per_user_per_day");
+        let check = check_settings(Some(&item));
+        assert!(!check.ok);
+        assert!(check
+            .error
+            .unwrap()
+            .starts_with("The settings can't be read"));
+    }
+}
@@ -0,0 +1,45 @@
+pub mod ban_rules;
+pub mod blocklists;
+pub mod clock;
+pub mod config;
+pub mod consistency;
+pub mod cors;
+pub mod dynamodb;
+pub mod encoding;
+pub mod filter_lists;
+pub mod health;
+pub mod migrations;
+pub mod openapi;
+pub mod rate_limits;
+pub mod reputation;
+pub mod router;
+pub mod routes;
+pub mod scoring;
+pub mod shadow_bans;
+pub mod snapshots;
+pub mod stats;
+pub mod telemetry;
+pub mod types;
+pub mod validate;
+pub mod vote_bursts;
+
+use aws_sdk_dynamodb::Client;
+use config::load_config;
+use telemetry::init_logging;
+use types::Config;
+
+/// Shared by the request handler and the scheduled jobs
+pub async fn setup() -> (Config, Client) {
+    let config = load_config().unwrap_or_else(|errors| panic!("ERROR: {}", errors));
+    init_logging(&config.log_level, &config.log_format);
+
+    let sdk_config = aws_config::load_from_env().await;
+    let mut dynamo_config_builder = aws_sdk_dynamodb::config::Builder::from(&sdk_config);
+    if config.use_local_database {
+        dynamo_config_builder = dynamo_config_builder.endpoint_url("http://localhost:8000");
+    }
+    let dynamo_config = dynamo_config_builder.build();
+    let dynamo_db_client = Client::from_conf(dynamo_config);
+
+    (config, dynamo_db_client)
+}
@@ -0,0 +1,317 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    clock::Clock,
+    dynamodb::*,
+    reputation::DEFAULT_REPUTATION,
+    snapshots::{item_from_json, item_to_json},
+    types::{
+        database::{schema_version, MigrationProgress, SCHEMA_VERSION},
+        Config,
+    },
+};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, AttributeValue::*},
+    types::SdkError,
+    Client,
+};
+use chrono::SecondsFormat;
+use futures::{stream, StreamExt, TryStreamExt};
+use lambda_http::Error;
+
+// Keeps well below the table's write capacity while still being quick
+const CONCURRENT_WRITES: usize = 25;
+// An item that keeps changing under a migration is probably being voted on
+const MAXIMUM_ATTEMPTS: u32 = 3;
+
+type Item = HashMap<String, AttributeValue>;
+
+/// Brings an item from `version - 1` up to `version`. Migrations are run on any
+/// item of an older version, so they need to be safe to run on every entity type
+/// and on items that already have what they add.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub migrate: fn(&mut Item) -> Result<(), Error>,
+}
+
+/// Every migration in order, the last one's version is `SCHEMA_VERSION`
+pub const MIGRATIONS: [Migration; 1] = [Migration {
+    version: 1,
+    description: "Fill in the reputation attributes that items from before reputations go without",
+    migrate: fill_in_reputations,
+}];
+
+fn fill_in_reputations(item: &mut Item) -> Result<(), Error> {
+    // Items read through an index may not have their entity_type
+    let entity_type = match item.get("entity_type") {
+        Some(entity_type) => entity_type
+            .as_s()
+            .or(Err("entity_type is not a string"))?
+            .clone(),
+        None => return Ok(()),
+    };
+    match entity_type.as_str() {
+        "Vote" | "ShadowVote" => {
+            item.entry("weight".to_string())
+                .or_insert_with(|| N(DEFAULT_REPUTATION.to_string()));
+        }
+        "User" => {
+            item.entry("reputation".to_string())
+                .or_insert_with(|| N(DEFAULT_REPUTATION.to_string()));
+        }
+        "LinkDetail" => {
+            let sum_of_votes = item.get("sum_of_votes").ok_or("No sum_of_votes")?.clone();
+            item.entry("weighted_sum_of_votes".to_string())
+                .or_insert(sum_of_votes);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The item after every migration newer than its `schema_version`, or `None`
+/// if it's already up to date
+pub fn migrate_item(item: &Item) -> Result<Option<Item>, Error> {
+    let from_version = schema_version(item)?;
+    if from_version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+    let mut migrated = item.clone();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > from_version)
+    {
+        (migration.migrate)(&mut migrated)?;
+    }
+    migrated.insert("schema_version".to_string(), N(SCHEMA_VERSION.to_string()));
+    Ok(Some(migrated))
+}
+
+/// Migrate every item in the table that's older than `SCHEMA_VERSION`. Progress
+/// is saved after each page of the scan, so an interrupted run carries on from
+/// where it stopped, and a completed run does nothing unless `restart` is set.
+pub async fn run_migrations(
+    restart: bool,
+    clock: &dyn Clock,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<MigrationProgress, Error> {
+    let saved_progress = dynamo_db_client
+        .get_item()
+        .table_name(&config.table_name)
+        .set_key(Some(get_migration_progress(SCHEMA_VERSION)))
+        .consistent_read(true)
+        .send()
+        .await?;
+    let now = || clock.now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut progress = match saved_progress.item() {
+        Some(item) if !restart => MigrationProgress::try_from(item)?,
+        _ => MigrationProgress {
+            target_version: SCHEMA_VERSION,
+            last_evaluated_key: None,
+            count_of_scanned_items: 0,
+            count_of_migrated_items: 0,
+            started_at: now(),
+            updated_at: now(),
+            completed_at: None,
+        },
+    };
+    if progress.completed_at.is_some() {
+        return Ok(progress);
+    }
+
+    let mut exclusive_start_key = match &progress.last_evaluated_key {
+        Some(last_evaluated_key) => {
+            Some(item_from_json(&serde_json::from_str(last_evaluated_key)?)?)
+        }
+        None => None,
+    };
+    loop {
+        let response = dynamo_db_client
+            .scan()
+            .table_name(&config.table_name)
+            .consistent_read(true)
+            .filter_expression(
+                "attribute_not_exists(schema_version) OR schema_version < :schema_version",
+            )
+            .expression_attribute_values(":schema_version", N(SCHEMA_VERSION.to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        let items = response.items().unwrap_or_default();
+        let results: Vec<bool> = stream::iter(items)
+            .map(|item| migrate_stored_item(item, config, dynamo_db_client))
+            .buffer_unordered(CONCURRENT_WRITES)
+            .try_collect()
+            .await?;
+        progress.count_of_scanned_items += items.len() as u64;
+        progress.count_of_migrated_items +=
+            results.iter().filter(|migrated| **migrated).count() as u64;
+
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        progress.last_evaluated_key = match &exclusive_start_key {
+            Some(key) => Some(item_to_json(key)?.to_string()),
+            None => None,
+        };
+        progress.updated_at = now();
+        if exclusive_start_key.is_none() {
+            progress.completed_at = Some(now());
+        }
+        dynamo_db_client
+            .transact_write_items()
+            .transact_items(put_migration_progress(&progress, config)?)
+            .send()
+            .await?;
+        if exclusive_start_key.is_none() {
+            return Ok(progress);
+        }
+    }
+}
+
+// Returns false if the item no longer needed migrating by the time it was written
+async fn migrate_stored_item(
+    item: &Item,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<bool, Error> {
+    let mut item = item.clone();
+    for _ in 0..MAXIMUM_ATTEMPTS {
+        let migrated = match migrate_item(&item)? {
+            Some(migrated) => migrated,
+            None => return Ok(false),
+        };
+        if update_migrated_item(&item, &migrated, config, dynamo_db_client).await? {
+            return Ok(true);
+        }
+        // Something else wrote to the item, so start again from what's there now
+        let response = dynamo_db_client
+            .get_item()
+            .table_name(&config.table_name)
+            .key("PK", item.get("PK").ok_or("No PK")?.clone())
+            .key("SK", item.get("SK").ok_or("No SK")?.clone())
+            .consistent_read(true)
+            .send()
+            .await?;
+        item = match response.item() {
+            Some(item) => item.clone(),
+            None => return Ok(false),
+        };
+    }
+    Err(format!(
+        "Item kept changing while being migrated [PK={:?}, SK={:?}]",
+        item.get("PK"),
+        item.get("SK")
+    )
+    .into())
+}
+
+// Only the attributes the migrations changed are written, and only if they
+// haven't changed since the item was read, so concurrent writes are never lost.
+// Returns false if the item changed.
+async fn update_migrated_item(
+    original: &Item,
+    migrated: &Item,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<bool, Error> {
+    let changed_attributes: BTreeSet<&String> = original
+        .keys()
+        .chain(migrated.keys())
+        .filter(|name| original.get(*name) != migrated.get(*name))
+        .collect();
+
+    let mut set_expressions = vec![];
+    let mut remove_expressions = vec![];
+    let mut conditions = vec!["attribute_exists(PK)".to_string()];
+    let mut names = HashMap::new();
+    let mut values = HashMap::new();
+    for (index, name) in changed_attributes.into_iter().enumerate() {
+        names.insert(format!("#attribute{}", index), name.clone());
+        match migrated.get(name) {
+            Some(value) => {
+                set_expressions.push(format!("#attribute{0} = :value{0}", index));
+                values.insert(format!(":value{}", index), value.clone());
+            }
+            None => remove_expressions.push(format!("#attribute{}", index)),
+        }
+        match original.get(name) {
+            Some(value) => {
+                conditions.push(format!("#attribute{0} = :original{0}", index));
+                values.insert(format!(":original{}", index), value.clone());
+            }
+            None => conditions.push(format!("attribute_not_exists(#attribute{})", index)),
+        }
+    }
+    let mut update_expression = format!("SET {}", set_expressions.join(","));
+    if !remove_expressions.is_empty() {
+        update_expression += &format!(" REMOVE {}", remove_expressions.join(","));
+    }
+
+    let result = dynamo_db_client
+        .update_item()
+        .table_name(&config.table_name)
+        .key("PK", original.get("PK").ok_or("No PK")?.clone())
+        .key("SK", original.get("SK").ok_or("No SK")?.clone())
+        .update_expression(update_expression)
+        .condition_expression(conditions.join(" AND "))
+        .set_expression_attribute_names(Some(names))
+        .set_expression_attribute_values(Some(values))
+        .send()
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        Err(SdkError::ServiceError(error))
+            if error.err().is_conditional_check_failed_exception() =>
+        {
+            Ok(false)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_in_order() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as u32 + 1);
+        }
+        assert_eq!(MIGRATIONS.last().unwrap().version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_item() {
+        let link_detail = HashMap::from([
+            ("PK".to_string(), S("link#example.com".to_string())),
+            ("SK".to_string(), S("link#example.com".to_string())),
+            ("entity_type".to_string(), S("LinkDetail".to_string())),
+            ("count_of_votes".to_string(), N("3".to_string())),
+            ("sum_of_votes".to_string(), N("-3".to_string())),
+        ]);
+        let migrated = migrate_item(&link_detail).unwrap().unwrap();
+        assert_eq!(migrated["weighted_sum_of_votes"], N("-3".to_string()));
+        assert_eq!(migrated["schema_version"], N(SCHEMA_VERSION.to_string()));
+
+        // Running again changes nothing
+        assert_eq!(migrate_item(&migrated).unwrap(), None);
+
+        // Existing attributes are kept
+        let mut user = HashMap::from([
+            ("PK".to_string(), S("user#1".to_string())),
+            ("entity_type".to_string(), S("User".to_string())),
+            ("reputation".to_string(), N("0.5".to_string())),
+        ]);
+        let migrated = migrate_item(&user).unwrap().unwrap();
+        assert_eq!(migrated["reputation"], N("0.5".to_string()));
+
+        // Items from a newer version are rejected rather than misread
+        user.insert(
+            "schema_version".to_string(),
+            N((SCHEMA_VERSION + 1).to_string()),
+        );
+        assert!(migrate_item(&user).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! The OpenAPI document for the API, served at `/v1/openapi.json`. The paths are
+//! declared on the empty functions below, and a test checks that they match the
+//! routes in `routes::api_router`.
+#![allow(dead_code)]
+
+use crate::types::{
+    api::{
+        Check, ConfigSummary, DayStats, Error, Health, QueuedVoteRequest, Readiness, ScoresRequest,
+        Stats, VoteRequest, VoteResult, VotesRequest,
+    },
+    Dimension, Link, LinkScore, Score, VoteReason,
+};
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Discontent",
+        description = "Community scores for the websites in search results"
+    ),
+    paths(
+        get_scores,
+        post_scores,
+        get_link,
+        post_vote,
+        post_votes,
+        get_filter_list,
+        post_admin_filter_list,
+        get_stats,
+        get_health,
+        get_health_ready,
+        get_openapi
+    ),
+    components(schemas(
+        Link,
+        Score,
+        VoteReason,
+        Dimension,
+        LinkScore,
+        ScoresRequest,
+        VoteRequest,
+        QueuedVoteRequest,
+        VotesRequest,
+        VoteResult,
+        Stats,
+        DayStats,
+        Health,
+        ConfigSummary,
+        Readiness,
+        Check,
+        Error
+    ))
+)]
+pub struct ApiDoc;
+
+/// Scores for up to 100 links
+#[utoipa::path(
+    get,
+    path = "/v1/scores",
+    params(("from" = String, Query, description = "A `ScoresRequest` as JSON")),
+    responses(
+        (status = 200, description = "A score for each link, in request order", body = [LinkScore]),
+        (status = 500, body = Error)
+    )
+)]
+fn get_scores() {}
+
+/// The same as `GET /v1/scores`, without the URL length limits
+#[utoipa::path(
+    post,
+    path = "/v1/scores",
+    request_body = ScoresRequest,
+    responses(
+        (status = 200, description = "A score for each link, in request order", body = [LinkScore]),
+        (status = 500, body = Error)
+    )
+)]
+fn post_scores() {}
+
+/// The score of a single link, on every dimension
+#[utoipa::path(
+    get,
+    path = "/v1/links/{hostname}",
+    params(("hostname" = String, Path, description = "The link's hostname")),
+    responses((status = 200, body = LinkScore), (status = 500, body = Error))
+)]
+fn get_link() {}
+
+#[utoipa::path(
+    post,
+    path = "/v1/vote",
+    request_body = VoteRequest,
+    responses(
+        (status = 200, description = "The vote was counted"),
+        (status = 429, description = "Too many requests from the IP this minute", body = Error),
+        (status = 500, body = Error)
+    )
+)]
+fn post_vote() {}
+
+/// Votes that the extension queued while offline
+#[utoipa::path(
+    post,
+    path = "/v1/votes",
+    request_body = VotesRequest,
+    responses(
+        (status = 200, description = "A result for each vote, in request order", body = [VoteResult]),
+        (status = 429, description = "Too many requests from the IP this minute", body = Error),
+        (status = 500, body = Error)
+    )
+)]
+fn post_votes() {}
+
+/// Every `Bad` link as a plain text filter list, updated daily
+#[utoipa::path(
+    get,
+    path = "/v1/filter-list",
+    params(("format" = String, Query, description = "`ublacklist`, `hosts` or `adblock`")),
+    responses(
+        (status = 200, description = "The filter list", body = String, content_type = "text/plain"),
+        (status = 500, body = Error)
+    )
+)]
+fn get_filter_list() {}
+
+/// Regenerates the filter list now, with `Authorization: Bearer <ADMIN_KEY>`
+#[utoipa::path(
+    post,
+    path = "/v1/admin/filter-list",
+    responses(
+        (status = 200, description = "`{\"count_of_links\": 12}`"),
+        (status = 401, body = Error),
+        (status = 404, description = "There's no `ADMIN_KEY`, so the route is off"),
+        (status = 429, body = Error),
+        (status = 500, body = Error)
+    )
+)]
+fn post_admin_filter_list() {}
+
+/// How the dataset is growing, updated hourly
+#[utoipa::path(
+    get,
+    path = "/v1/stats",
+    responses((status = 200, body = Stats), (status = 500, body = Error))
+)]
+fn get_stats() {}
+
+/// The version and config that are running, for uptime monitors
+#[utoipa::path(
+    get,
+    path = "/v1/health",
+    responses((status = 200, body = Health))
+)]
+fn get_health() {}
+
+/// Whether the table and its settings can be read, for load balancers
+#[utoipa::path(
+    get,
+    path = "/v1/health/ready",
+    responses(
+        (status = 200, description = "Every check passed", body = Readiness),
+        (status = 503, description = "At least one check failed", body = Readiness)
+    )
+)]
+fn get_health_ready() {}
+
+/// This document
+#[utoipa::path(
+    get,
+    path = "/v1/openapi.json",
+    responses((status = 200, description = "The OpenAPI document"))
+)]
+fn get_openapi() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::api_router;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_spec_matches_routes() {
+        let spec: serde_json::Value =
+            serde_json::from_str(&ApiDoc::openapi().to_json().unwrap()).unwrap();
+        let documented: BTreeSet<(String, String)> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, operations)| {
+                operations
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .map(|method| (method.to_uppercase(), path.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let routed: BTreeSet<(String, String)> = api_router(false)
+            .routes()
+            .iter()
+            .map(|route| (route.method.to_string(), route.path.clone()))
+            .collect();
+        assert_eq!(documented, routed);
+    }
+
+    #[test]
+    fn test_schemas_match_requests() {
+        let spec: serde_json::Value =
+            serde_json::from_str(&ApiDoc::openapi().to_json().unwrap()).unwrap();
+        let schemas = &spec["components"]["schemas"];
+        // Only the fields without a default are required by the handlers
+        assert_eq!(
+            schemas["VoteRequest"]["required"],
+            serde_json::json!(["link", "value", "user_id"])
+        );
+        assert_eq!(
+            schemas["ScoresRequest"]["required"],
+            serde_json::json!(["links"])
+        );
+        assert_eq!(
+            schemas["Score"]["enum"],
+            serde_json::json!(["Good", "Bad", "Controversial", "NoScore"])
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{
+    router::{error_response, Context, MiddlewareFuture},
+    types::{database::SCHEMA_VERSION, Config},
+};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue::*, ReturnValue},
+    Client,
+};
+use chrono::{DateTime, Duration, DurationRound, Timelike, Utc};
+use lambda_http::{
+    http::{header::RETRY_AFTER, StatusCode},
+    request::RequestContext,
+    Body, Error, Request, Response,
+};
+use tracing::*;
+
+/// Counts the requests from each IP in the current minute, and turns away the
+/// ones over `RATE_LIMIT_PER_MINUTE`. Off when that's 0.
+pub fn rate_limit<'a>(context: &'a Context<'_>) -> MiddlewareFuture<'a> {
+    Box::pin(async move {
+        if context.config.rate_limit_per_minute == 0 {
+            return Ok(None);
+        }
+        // Requests that didn't come through API Gateway, like in tests, have no IP
+        let source_ip = match source_ip(&context.request) {
+            Some(source_ip) => source_ip,
+            None => return Ok(None),
+        };
+        let now = context.clock.now();
+        let count_of_requests =
+            increment_request_count(&source_ip, now, context.config, context.dynamo_db_client)
+                .await?;
+        let response =
+            check_rate_limit(count_of_requests, context.config.rate_limit_per_minute, now);
+        if response.is_some() {
+            info!(
+                "Rate limited a request [source_ip={}, count_of_requests={}]",
+                source_ip, count_of_requests
+            );
+        }
+        Ok(response)
+    })
+}
+
+/// A 429 once the minute's requests are over the limit, retried next minute
+pub fn check_rate_limit(
+    count_of_requests: u32,
+    rate_limit_per_minute: u32,
+    now: DateTime<Utc>,
+) -> Option<Response<Body>> {
+    if count_of_requests <= rate_limit_per_minute {
+        return None;
+    }
+    let mut response = error_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests");
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, (60 - now.second()).into());
+    Some(response)
+}
+
+/// The key of the item that counts an IP's requests in the minute of `now`
+pub fn rate_limit_key(source_ip: &str, now: DateTime<Utc>) -> (String, String) {
+    (
+        format!("rate-limit#{}", source_ip),
+        format!("minute#{}", now.format("%Y-%m-%dT%H:%M")),
+    )
+}
+
+fn source_ip(request: &Request) -> Option<String> {
+    match request.extensions().get::<RequestContext>()? {
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.clone(),
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.clone(),
+        _ => None,
+    }
+}
+
+// The count of requests in the minute so far, including this one. DynamoDB
+// deletes the item a minute after the window ends.
+async fn increment_request_count(
+    source_ip: &str,
+    now: DateTime<Utc>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<u32, Error> {
+    let (partition_key, sort_key) = rate_limit_key(source_ip, now);
+    let expires_at = now.duration_trunc(Duration::minutes(1))? + Duration::minutes(2);
+    let response = dynamo_db_client
+        .update_item()
+        .table_name(&config.table_name)
+        .key("PK", S(partition_key))
+        .key("SK", S(sort_key))
+        .update_expression(format!(
+            "ADD {} SET {},{},{}",
+            "count_of_requests :one",
+            "entity_type = :entity_type",
+            "schema_version = if_not_exists(schema_version, :schema_version)",
+            "time_to_live = :time_to_live",
+        ))
+        .expression_attribute_values(":one", N(1.to_string()))
+        .expression_attribute_values(":entity_type", S("RateLimit".to_string()))
+        .expression_attribute_values(":schema_version", N(SCHEMA_VERSION.to_string()))
+        .expression_attribute_values(":time_to_live", N(expires_at.timestamp().to_string()))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await?;
+    Ok(response
+        .attributes()
+        .and_then(|attributes| attributes.get("count_of_requests"))
+        .ok_or("No count_of_requests")?
+        .as_n()
+        .or(Err("count_of_requests is not a number"))?
+        .parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_rate_limit() {
+        let now = DateTime::parse_from_rfc3339("2023-02-08T10:15:45Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            rate_limit_key("192.0.2.1", now),
+            (
+                "rate-limit#192.0.2.1".to_string(),
+                "minute#2023-02-08T10:15".to_string()
+            )
+        );
+
+        assert!(check_rate_limit(60, 60, now).is_none());
+        let response = check_rate_limit(61, 60, now).unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "15");
+    }
+}
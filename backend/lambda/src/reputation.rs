@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use crate::{
+    dynamodb::*,
+    scoring::overall_score,
+    types::{database::LinkDetail, Config, Link, Score},
+};
+use aws_sdk_dynamodb::{model::AttributeValue::*, Client};
+use chrono::{DateTime, Utc};
+use lambda_http::Error;
+use tracing::*;
+
+/// Reputation of users that existed before reputations were introduced
+pub const DEFAULT_REPUTATION: f64 = 1.0;
+const MAXIMUM_REPUTATION: f64 = 2.0;
+// New users start at a quarter of the influence and ramp up over a month
+const MINIMUM_AGE_FACTOR: f64 = 0.25;
+const DAYS_UNTIL_ESTABLISHED: f64 = 30.0;
+// Agreement with consensus is ignored until there's enough of it to go on
+const MINIMUM_DECIDED_VOTES: u32 = 5;
+
+/// A weight between 0 and 2 applied to each of the user's votes. An established
+/// user with no track record gets 1, the same as before reputations existed.
+pub fn calculate_reputation(
+    account_age_in_days: f64,
+    count_of_agreeing_votes: u32,
+    count_of_disagreeing_votes: u32,
+    is_banned: bool,
+    count_of_bans: u32,
+) -> f64 {
+    if is_banned {
+        return 0.0;
+    }
+    let age_factor = MINIMUM_AGE_FACTOR
+        + (1.0 - MINIMUM_AGE_FACTOR)
+            * (account_age_in_days / DAYS_UNTIL_ESTABLISHED).clamp(0.0, 1.0);
+    let count_of_decided_votes = count_of_agreeing_votes + count_of_disagreeing_votes;
+    let agreement = if count_of_decided_votes < MINIMUM_DECIDED_VOTES {
+        0.5
+    } else {
+        count_of_agreeing_votes as f64 / count_of_decided_votes as f64
+    };
+    // Halved for every time the user has been banned in the past
+    let ban_factor = 0.5_f64.powi(count_of_bans as i32);
+
+    let reputation = (age_factor * 2.0 * agreement * ban_factor).clamp(0.0, MAXIMUM_REPUTATION);
+    (reputation * 100.0).round() / 100.0
+}
+
+/// Reputation for a user's very first vote
+pub fn new_user_reputation() -> f64 {
+    calculate_reputation(0.0, 0, 0, false, 0)
+}
+
+/// The direction the crowd has settled on for a link, if it has
+pub fn consensus(link_detail: &LinkDetail, config: &Config) -> Option<i32> {
+    match overall_score(link_detail, config.use_weighted_scores) {
+        Score::Good => Some(1),
+        Score::Bad => Some(-1),
+        Score::Controversial | Score::NoScore => None,
+    }
+}
+
+/// Recompute the reputation of every user from their account age, how often they
+/// agree with the eventual consensus on the links they voted for, and their bans.
+/// Returns the number of users that were updated.
+pub async fn update_reputations(
+    config: &Config,
+    dynamo_db_client: &Client,
+    now: DateTime<Utc>,
+) -> Result<u32, Error> {
+    let mut consensus_by_link: HashMap<Link, Option<i32>> = HashMap::new();
+    let mut count_of_updated_users = 0;
+
+    for (user_id, user) in scan_users(config, dynamo_db_client).await? {
+        let votes = query_user_votes(&user_id, config, dynamo_db_client).await?;
+
+        let unknown_links: Vec<Link> = votes
+            .iter()
+            .map(|vote| vote.link.clone())
+            .filter(|link| !consensus_by_link.contains_key(link))
+            .collect();
+        for (link, link_detail) in
+            get_link_details(&unknown_links, config, dynamo_db_client).await?
+        {
+            consensus_by_link.insert(link, consensus(&link_detail, config));
+        }
+
+        let mut count_of_agreeing_votes = 0;
+        let mut count_of_disagreeing_votes = 0;
+        for vote in &votes {
+            match consensus_by_link.get(&vote.link).copied().flatten() {
+                Some(consensus) if consensus == vote.value => count_of_agreeing_votes += 1,
+                Some(_) => count_of_disagreeing_votes += 1,
+                None => {}
+            }
+        }
+
+        let created_at = DateTime::parse_from_rfc3339(&user.created_at)?.with_timezone(&Utc);
+        let account_age_in_days = (now - created_at).num_seconds() as f64 / 86_400.0;
+        let reputation = calculate_reputation(
+            account_age_in_days,
+            count_of_agreeing_votes,
+            count_of_disagreeing_votes,
+            user.is_banned,
+            user.count_of_bans,
+        );
+        if reputation != user.reputation {
+            debug!(
+                "Updating reputation [user_id={}, from={}, to={}]",
+                user_id, user.reputation, reputation
+            );
+            dynamo_db_client
+                .update_item()
+                .table_name(&config.table_name)
+                .set_key(Some(get_user(&user_id)))
+                .update_expression("SET reputation = :reputation")
+                .expression_attribute_values(":reputation", N(reputation.to_string()))
+                .send()
+                .await?;
+            count_of_updated_users += 1;
+        }
+    }
+
+    Ok(count_of_updated_users)
+}
+
+async fn get_link_details(
+    links: &[Link],
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<(Link, LinkDetail)>, Error> {
+    let keys = links.iter().map(get_link_detail).collect();
+    let mut link_details = vec![];
+    for item in batch_get_items(keys, config, dynamo_db_client).await? {
+        let link_detail = LinkDetail::try_from(&item)?;
+        link_details.push((link_detail.link.clone(), link_detail));
+    }
+    Ok(link_details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate_reputation() {
+        // Established users without a track record count the same as before
+        assert_eq!(
+            calculate_reputation(365.0, 0, 0, false, 0),
+            DEFAULT_REPUTATION
+        );
+        assert_eq!(
+            calculate_reputation(365.0, 2, 2, false, 0),
+            DEFAULT_REPUTATION
+        );
+
+        // Brand new users have a quarter of the influence, ramping up over a month
+        assert_eq!(new_user_reputation(), 0.25);
+        assert_eq!(calculate_reputation(15.0, 0, 0, false, 0), 0.63);
+        assert_eq!(calculate_reputation(30.0, 0, 0, false, 0), 1.0);
+
+        // Agreeing with consensus increases influence, disagreeing decreases it
+        assert_eq!(calculate_reputation(365.0, 100, 0, false, 0), 2.0);
+        assert_eq!(calculate_reputation(365.0, 75, 25, false, 0), 1.5);
+        assert_eq!(calculate_reputation(365.0, 5, 95, false, 0), 0.1);
+
+        // Past bans halve it each time, current bans remove it entirely
+        assert_eq!(calculate_reputation(365.0, 0, 0, false, 1), 0.5);
+        assert_eq!(calculate_reputation(365.0, 0, 0, false, 2), 0.25);
+        assert_eq!(calculate_reputation(365.0, 100, 0, true, 0), 0.0);
+    }
+}
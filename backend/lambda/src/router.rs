@@ -0,0 +1,365 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    pin::Pin,
+    time::Instant,
+};
+
+use crate::{
+    clock::Clock,
+    telemetry::{emit_metrics, Unit},
+    types::{api, Config},
+};
+use aws_sdk_dynamodb::Client;
+use lambda_http::{
+    http::{
+        header::{ALLOW, AUTHORIZATION, CONTENT_TYPE},
+        Method, StatusCode,
+    },
+    Body, Error, Request, Response,
+};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tracing::*;
+
+/// Everything a handler gets, with the values of the `{parameters}` in its path
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub request: Request,
+    pub parameters: HashMap<String, String>,
+    pub clock: &'a dyn Clock,
+    pub config: &'a Config,
+    pub dynamo_db_client: &'a Client,
+}
+
+pub type HandlerFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Response<Body>, Error>> + Send + 'a>>;
+pub type Handler = for<'a> fn(Context<'a>) -> HandlerFuture<'a>;
+pub type MiddlewareFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<Response<Body>>, Error>> + Send + 'a>>;
+/// Runs before the handler, and returns a response to stop the request there
+pub type Middleware = for<'a, 'b> fn(&'b Context<'a>) -> MiddlewareFuture<'b>;
+
+pub struct Route {
+    pub method: Method,
+    /// The full path, including the router's prefix
+    pub path: String,
+    middleware: Vec<Middleware>,
+    handler: Handler,
+}
+
+#[derive(Default)]
+pub struct Router {
+    prefix: String,
+    routes: Vec<Route>,
+}
+impl Router {
+    /// Every route is under `prefix`, e.g. `/v1`
+    pub fn new(prefix: &str) -> Self {
+        Router {
+            prefix: prefix.to_string(),
+            routes: vec![],
+        }
+    }
+
+    pub fn route(self, method: Method, path: &str, handler: Handler) -> Self {
+        self.route_with(method, path, vec![], handler)
+    }
+
+    pub fn route_with(
+        mut self,
+        method: Method,
+        path: &str,
+        middleware: Vec<Middleware>,
+        handler: Handler,
+    ) -> Self {
+        self.routes.push(Route {
+            method,
+            path: format!("{}{}", self.prefix, path),
+            middleware,
+            handler,
+        });
+        self
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Runs the matching route. Errors become a 500 with the error message, a
+    /// known path with the wrong method is a 405, and anything else is a 404.
+    /// Each request is logged and its latency emitted as a metric per route.
+    pub async fn handle(
+        &self,
+        request: Request,
+        clock: &dyn Clock,
+        config: &Config,
+        dynamo_db_client: &Client,
+    ) -> Response<Body> {
+        let started_at = Instant::now();
+        let method = request.method().clone();
+        let (route, response) = self
+            .dispatch(request, clock, config, dynamo_db_client)
+            .await;
+        let latency = started_at.elapsed().as_secs_f64() * 1000.0;
+        // Unmatched paths are grouped together so they can't flood the metrics
+        let route = route.unwrap_or("unmatched");
+        info!(
+            route,
+            method = %method,
+            status = response.status().as_u16(),
+            latency_ms = latency,
+            "Request completed"
+        );
+        emit_metrics(
+            &[("Route", route)],
+            &[("Latency", latency, Unit::Milliseconds)],
+        );
+        response
+    }
+
+    // The path of the route that handled the request, if any, and the response
+    async fn dispatch(
+        &self,
+        request: Request,
+        clock: &dyn Clock,
+        config: &Config,
+        dynamo_db_client: &Client,
+    ) -> (Option<&str>, Response<Body>) {
+        let path = request.uri().path().to_string();
+        let matching_routes: Vec<(&Route, HashMap<String, String>)> = self
+            .routes
+            .iter()
+            .filter_map(|route| {
+                match_path(&route.path, &path).map(|parameters| (route, parameters))
+            })
+            .collect();
+        if matching_routes.is_empty() {
+            return (None, status_response(StatusCode::NOT_FOUND));
+        }
+        let (route, parameters) = match matching_routes
+            .iter()
+            .find(|(route, _)| route.method == request.method())
+        {
+            Some(matching_route) => matching_route.clone(),
+            None => {
+                let allowed_methods: Vec<&str> = matching_routes
+                    .iter()
+                    .map(|(route, _)| route.method.as_str())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect();
+                let mut response = status_response(StatusCode::METHOD_NOT_ALLOWED);
+                response
+                    .headers_mut()
+                    .insert(ALLOW, allowed_methods.join(", ").parse().unwrap());
+                return (None, response);
+            }
+        };
+
+        let context = Context {
+            request,
+            parameters,
+            clock,
+            config,
+            dynamo_db_client,
+        };
+        for middleware in &route.middleware {
+            match middleware(&context).await {
+                Ok(None) => {}
+                Ok(Some(response)) => return (Some(&route.path), response),
+                Err(e) => {
+                    warn!("Could not run middleware [error={:#?}]", e);
+                    return (
+                        Some(&route.path),
+                        error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+                    );
+                }
+            }
+        }
+        let response = match (route.handler)(context).await {
+            Ok(mut response) => {
+                // Handlers can set their own content-type (e.g. for CBOR scores), otherwise it's JSON
+                if !response.headers().contains_key(CONTENT_TYPE) {
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+                }
+                response
+            }
+            // TODO: Handle the HTTP errors better than just chucking them
+            // all into a 500 response
+            Err(e) => {
+                warn!("Could not complete request [error={:#?}]", e);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+            }
+        };
+        (Some(&route.path), response)
+    }
+}
+
+/// The values of the `{parameters}` in `pattern` if `path` matches it
+fn match_path(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let pattern_segments: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let path_segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    if pattern_segments.len() != path_segments.len() {
+        return None;
+    }
+    let mut parameters = HashMap::new();
+    for (pattern_segment, path_segment) in pattern_segments.iter().zip(path_segments) {
+        match pattern_segment
+            .strip_prefix('{')
+            .and_then(|name| name.strip_suffix('}'))
+        {
+            Some(name) if !path_segment.is_empty() => {
+                parameters.insert(name.to_string(), path_segment.to_string());
+            }
+            Some(_) => return None,
+            None if *pattern_segment == path_segment => {}
+            None => return None,
+        }
+    }
+    Some(parameters)
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::Empty);
+    *response.status_mut() = status;
+    response
+}
+
+pub fn error_response(status: StatusCode, error: &str) -> Response<Body> {
+    let error_body = serde_json::to_string(&api::Error {
+        error: error.to_string(),
+    })
+    .unwrap_or(r#"{"error":"Something bad and unknown"}"#.to_string());
+    let mut response = Response::new(Body::from(error_body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+/// Only lets through requests with `Authorization: Bearer <ADMIN_KEY>`. Without
+/// an `ADMIN_KEY` the route is turned off.
+pub fn require_admin_key<'a>(context: &'a Context<'_>) -> MiddlewareFuture<'a> {
+    Box::pin(async move { Ok(check_admin_key(&context.request, context.config)) })
+}
+
+fn check_admin_key(request: &Request, config: &Config) -> Option<Response<Body>> {
+    let admin_key = match &config.admin_key {
+        Some(admin_key) => admin_key,
+        None => return Some(status_response(StatusCode::NOT_FOUND)),
+    };
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+    match authorization {
+        Some(key) if keys_match(key, &admin_key.0) => None,
+        _ => Some(error_response(StatusCode::UNAUTHORIZED, "Not authorized")),
+    }
+}
+
+// Compares digests in constant time, so neither the length nor the contents of
+// the key can be guessed from how long a request takes
+fn keys_match(key: &str, admin_key: &str) -> bool {
+    Sha256::digest(key).ct_eq(&Sha256::digest(admin_key)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::SystemClock, config::config_from_sources};
+
+    async fn handle(router: &Router, method: Method, path: &str) -> Response<Body> {
+        let config = config_from_sources(
+            HashMap::from([
+                ("TABLE_NAME".to_string(), "Discontent".to_string()),
+                ("ADMIN_KEY".to_string(), "0123456789abcdef".to_string()),
+            ]),
+            None,
+        )
+        .unwrap();
+        let dynamo_db_client = Client::from_conf(aws_sdk_dynamodb::Config::builder().build());
+        let mut request = Request::new(Body::Empty);
+        *request.method_mut() = method;
+        *request.uri_mut() = path.parse().unwrap();
+        router
+            .handle(request, &SystemClock, &config, &dynamo_db_client)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_handle() {
+        let router = Router::new("/v1")
+            .route(Method::GET, "/links/{hostname}", |context| {
+                Box::pin(
+                    async move { Ok(Response::new(context.parameters["hostname"].clone().into())) },
+                )
+            })
+            .route(Method::POST, "/links/{hostname}", |_| {
+                Box::pin(async move { Err("Something went wrong".into()) })
+            })
+            // Shadowed by the first route, but its method is only allowed once
+            .route(Method::GET, "/links/example.com", |_| {
+                Box::pin(async move { Ok(Response::new(Body::Empty)) })
+            })
+            .route_with(Method::GET, "/admin", vec![require_admin_key], |_| {
+                Box::pin(async move { Ok(Response::new(Body::Empty)) })
+            });
+
+        let response = handle(&router, Method::GET, "/v1/links/example.com").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(response.body(), &Body::from("example.com"));
+
+        let response = handle(&router, Method::POST, "/v1/links/example.com").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            response.body(),
+            &Body::from(r#"{"error":"Something went wrong"}"#)
+        );
+
+        let response = handle(&router, Method::DELETE, "/v1/links/example.com").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET, POST");
+
+        let response = handle(&router, Method::GET, "/v1/unknown").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // No Authorization header
+        let response = handle(&router, Method::GET, "/v1/admin").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_keys_match() {
+        assert!(keys_match("secret", "secret"));
+        assert!(!keys_match("secret", "other"));
+        assert!(!keys_match("secre", "secret"));
+        assert!(!keys_match("", "secret"));
+    }
+
+    #[test]
+    fn test_match_path() {
+        assert_eq!(match_path("/v1/scores", "/v1/scores"), Some(HashMap::new()));
+        assert_eq!(
+            match_path("/v1/scores", "/v1/scores/"),
+            Some(HashMap::new())
+        );
+        assert_eq!(match_path("/v1/scores", "/v1/vote"), None);
+        assert_eq!(match_path("/v1/scores", "/v2/scores"), None);
+        assert_eq!(
+            match_path("/v1/links/{hostname}", "/v1/links/example.com"),
+            Some(HashMap::from([(
+                "hostname".to_string(),
+                "example.com".to_string()
+            )]))
+        );
+        assert_eq!(match_path("/v1/links/{hostname}", "/v1/links/"), None);
+        assert_eq!(match_path("/v1/links/{hostname}", "/v1/links/a/b"), None);
+    }
+}
@@ -1,48 +1,189 @@
 use std::collections::HashMap;
 
 use crate::{
+    ban_rules::{check_ban_rules_for_vote, recent_votes_received_at, BanAction},
+    clock::Clock,
     dynamodb::*,
+    encoding::negotiated_response,
+    filter_lists::{self, get_filter_list, render_filter_list, FilterListFormat},
+    health::{self, readiness},
+    openapi::ApiDoc,
+    rate_limits::rate_limit,
+    reputation::{new_user_reputation, DEFAULT_REPUTATION},
+    router::{require_admin_key, Context, Router},
     scoring::*,
-    types::{database::*, Config},
-    validate::{validate_get_scores_request, validate_vote_request},
+    stats::{get_stats, STATS_CACHE_MAX_AGE},
+    telemetry::{dynamodb_span, emit_metrics, emit_vote_metric, record_consumed_capacity, Unit},
+    types::{
+        api::{ScoresRequest, VoteResult},
+        database::*,
+        Config, Dimension, Link, LinkScore,
+    },
+    validate::{
+        validate_get_scores_request, validate_post_scores_request, validate_vote_request,
+        validate_votes_request,
+    },
 };
 use aws_sdk_dynamodb::{
-    model::{AttributeValue::*, KeysAndAttributes, TransactWriteItem},
+    model::{KeysAndAttributes, ReturnConsumedCapacity, TransactWriteItem},
     Client,
 };
-use chrono::{SecondsFormat, Utc};
-use lambda_http::{Body, Error, Request, RequestExt};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use lambda_http::{
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, LAST_MODIFIED},
+        Method, StatusCode,
+    },
+    Body, Error, RequestExt, Response,
+};
+use serde_json::json;
 use tracing::*;
+use utoipa::OpenApi;
 use validator::Validate;
 
+/// Every route in the API. `POST /v1/clock` is only for tests against a fake clock.
+pub fn api_router(clock_is_settable: bool) -> Router {
+    let router = Router::new("/v1")
+        .route(Method::GET, "/scores", |context| Box::pin(scores(context)))
+        .route(Method::POST, "/scores", |context| Box::pin(scores(context)))
+        .route(Method::GET, "/links/{hostname}", |context| {
+            Box::pin(link(context))
+        })
+        .route_with(Method::POST, "/vote", vec![rate_limit], |context| {
+            Box::pin(vote(context))
+        })
+        .route_with(Method::POST, "/votes", vec![rate_limit], |context| {
+            Box::pin(votes(context))
+        })
+        .route(Method::GET, "/stats", |context| Box::pin(stats(context)))
+        .route(Method::GET, "/health", |context| Box::pin(health(context)))
+        .route(Method::GET, "/health/ready", |context| {
+            Box::pin(ready(context))
+        })
+        .route(Method::GET, "/openapi.json", |_| Box::pin(openapi()))
+        .route(Method::GET, "/filter-list", |context| {
+            Box::pin(filter_list(context))
+        })
+        .route_with(
+            Method::POST,
+            "/admin/filter-list",
+            vec![require_admin_key, rate_limit],
+            |context| Box::pin(update_filter_list(context)),
+        );
+    match clock_is_settable {
+        true => router.route(Method::POST, "/clock", |context| {
+            Box::pin(set_clock(context))
+        }),
+        false => router,
+    }
+}
+
 #[instrument(level = "trace")]
-pub async fn vote(
-    request: Request,
-    config: &Config,
-    dynamo_db_client: &Client,
-) -> Result<Body, Error> {
+pub async fn vote(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let Context {
+        request,
+        clock,
+        config,
+        dynamo_db_client,
+        ..
+    } = context;
     let vote_request = validate_vote_request(request.body())?;
 
-    let created_at: String;
-    if config.use_system_time {
-        // Always use "2018-01-26T18:30:09Z" format
-        created_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
-    } else {
-        created_at = "2022-07-27T12:30:00Z".to_string(); // For testing, <3 bel
-    }
-
     let vote = Vote {
         link: vote_request.link.clone(),
-        user_id: vote_request.user_id.clone(),
+        user_id: vote_request.user_id,
         value: vote_request.value,
-        created_at,
+        // Always use "2018-01-26T18:30:09Z" format
+        created_at: clock.now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        reason: vote_request.reason,
+        dimension: vote_request.dimension,
+        weight: DEFAULT_REPUTATION, // Set from the user's reputation when submitted
     };
-    // Extract day string `2023-02-09`
+    let result = submit_vote(vote, false, clock, config, dynamo_db_client).await;
+    emit_vote_metric(&result);
+    result?;
+
+    Ok(Response::new(Body::Empty))
+}
+
+/// Votes that were queued by the extension while offline. Each one goes through
+/// the same checks as a single vote, and gets its own result so that partial
+/// failures can be retried precisely.
+#[instrument(level = "trace")]
+pub async fn votes(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let Context {
+        request,
+        clock,
+        config,
+        dynamo_db_client,
+        ..
+    } = context;
+    let votes_request = validate_votes_request(request.body())?;
+    let now = clock.now();
+    let oldest_allowed = now - Duration::days(config.maximum_queued_vote_age_in_days.into());
+
+    // Apply the votes in the order they were made, but report in request order
+    let mut order: Vec<usize> = (0..votes_request.votes.len()).collect();
+    order.sort_by_key(|&index| &votes_request.votes[index].created_at);
+
+    let mut results: Vec<Option<VoteResult>> = vec![None; votes_request.votes.len()];
+    for index in order {
+        let vote_request = &votes_request.votes[index];
+        let vote = Vote {
+            link: vote_request.link.clone(),
+            user_id: vote_request.user_id,
+            value: vote_request.value,
+            created_at: vote_request.created_at.clone(),
+            reason: vote_request.reason,
+            dimension: vote_request.dimension,
+            weight: DEFAULT_REPUTATION, // Set from the user's reputation when submitted
+        };
+        let created_at = DateTime::parse_from_rfc3339(&vote.created_at)?.with_timezone(&Utc);
+        let result = if created_at > now || created_at < oldest_allowed {
+            Err("Vote timestamp is out of range".into())
+        } else {
+            submit_vote(vote, false, clock, config, dynamo_db_client).await
+        };
+        emit_vote_metric(&result);
+        results[index] = Some(match result {
+            Ok(()) => VoteResult::success(),
+            Err(e) => {
+                info!("Queued vote was rejected [error={}]", e);
+                VoteResult::failure(e.to_string())
+            }
+        });
+    }
+    let results: Vec<VoteResult> = results.into_iter().flatten().collect();
+
+    Ok(Response::new(serde_json::to_string(&results)?.into()))
+}
+
+/// Apply a single vote, after checking it against the user and the settings.
+/// Replayed votes were already checked when they were received, so they skip
+/// the daily limit, the ban rules and `voting_is_disabled`.
+pub async fn submit_vote(
+    mut vote: Vote,
+    is_replay: bool,
+    clock: &dyn Clock,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<(), Error> {
+    // The vote is recorded on the day it was cast, `2023-02-09`, but it counts
+    // towards the daily limit on the day it was received
     let day = vote.created_at.clone()[..10].to_string();
+    let now = clock.now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let today = now[..10].to_string();
 
-    info!("New vote request: {:?}", vote);
+    info!(
+        link = %vote.link.hostname,
+        value = vote.value,
+        reason = ?vote.reason,
+        dimension = ?vote.dimension,
+        "New vote request"
+    );
 
     // Get settings and user history
+    let span = dynamodb_span("BatchGetItem");
     let settings_and_user_request = dynamo_db_client
         .batch_get_item()
         .request_items(
@@ -51,13 +192,22 @@ pub async fn vote(
                 .set_keys(Some(vec![
                     get_settings(),
                     get_user(&vote.user_id),
-                    get_daily_user_history(&day, &vote.user_id),
                     get_vote(&vote),
+                    get_shadow_vote(&vote),
+                    get_link_detail(&vote.link),
                 ]))
                 .build(),
         )
+        .return_consumed_capacity(ReturnConsumedCapacity::Total)
         .send()
+        .instrument(span.clone())
         .await?;
+    record_consumed_capacity(
+        &span,
+        settings_and_user_request
+            .consumed_capacity()
+            .unwrap_or_default(),
+    );
     debug!(
         "Settings and User History response: {:#?}",
         settings_and_user_request
@@ -65,11 +215,17 @@ pub async fn vote(
 
     let mut user_does_not_exist = true;
     let mut user_is_banned = false;
+    let mut user_is_shadow_banned = false;
     let mut first_vote_on_link_for_user = true;
     let mut voting_is_disabled = false;
-    let mut user_has_reached_max_vote_limit_for_today = false;
+    let mut count_of_votes_today: u32 = 0;
     let mut maximum_votes_per_user_per_day: u32 = 10;
+    let mut ban_rules = vec![];
     let mut old_vote: Option<Vote> = None;
+    let mut old_shadow_vote: Option<Vote> = None;
+    let mut reputation = new_user_reputation();
+    let mut previous_votes_received_at = vec![];
+    let mut sum_of_votes: Option<i32> = None;
     for item in settings_and_user_request
         .responses()
         .ok_or("DynamoDB request error")?
@@ -87,22 +243,29 @@ pub async fn vote(
                 let settings = Settings::try_from(item)?;
                 voting_is_disabled = settings.voting_is_disabled;
                 maximum_votes_per_user_per_day = settings.maximum_votes_per_user_per_day;
+                ban_rules = settings.ban_rules;
             }
             "User" => {
                 user_does_not_exist = false;
                 let user = User::try_from(item)?;
                 user_is_banned = user.is_banned;
-            }
-            "UserHistory" => {
-                let daily_user_history = UserHistory::try_from(item)?;
-                // Assumes the settings have already been retrieved
-                user_has_reached_max_vote_limit_for_today =
-                    daily_user_history.count_of_votes >= maximum_votes_per_user_per_day;
+                user_is_shadow_banned = user.is_shadow_banned;
+                reputation = user.reputation;
+                if user.last_vote_day == today {
+                    count_of_votes_today = user.count_of_votes_on_last_vote_day;
+                }
+                previous_votes_received_at = user.recent_votes_received_at;
             }
             "Vote" => {
                 first_vote_on_link_for_user = false;
                 old_vote = Some(Vote::try_from(item)?);
             }
+            "ShadowVote" => {
+                old_shadow_vote = Some(Vote::try_from(item)?);
+            }
+            "LinkDetail" => {
+                sum_of_votes = Some(LinkDetail::try_from(item)?.sum_of_votes);
+            }
             _ => {
                 return Err("Unknown entity_type".into());
             }
@@ -112,104 +275,366 @@ pub async fn vote(
     if user_is_banned {
         return Err("User is banned".into());
     }
-    if voting_is_disabled {
+    if voting_is_disabled && !is_replay {
         return Err("Voting is disabled".into());
     }
-    if first_vote_on_link_for_user && user_has_reached_max_vote_limit_for_today {
+    // Rate rules go by when votes arrive, since queued votes can claim any time
+    let recent_votes_received_at =
+        recent_votes_received_at(&previous_votes_received_at, clock.now(), &ban_rules)?;
+    if !user_does_not_exist && !user_is_shadow_banned && !is_replay {
+        if let Some(decision) = check_ban_rules_for_vote(&recent_votes_received_at, &ban_rules)? {
+            info!(
+                "Ban rule matched [user_id={}, decision={:?}]",
+                vote.user_id, decision
+            );
+            let span = dynamodb_span("TransactWriteItems");
+            let result = dynamo_db_client
+                .transact_write_items()
+                .transact_items(ban_user(&vote.user_id, &decision, &now, config))
+                .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                .send()
+                .instrument(span.clone())
+                .await?;
+            record_consumed_capacity(&span, result.consumed_capacity().unwrap_or_default());
+            match decision.action {
+                BanAction::Ban => return Err("User is banned".into()),
+                BanAction::ShadowBan => user_is_shadow_banned = true,
+            }
+        }
+    }
+    // Shadow votes count towards the limit too, otherwise it would give the ban away
+    let counts_towards_limit = if user_is_shadow_banned {
+        first_vote_on_link_for_user && old_shadow_vote.is_none()
+    } else {
+        first_vote_on_link_for_user
+    };
+    if !is_replay && counts_towards_limit && count_of_votes_today >= maximum_votes_per_user_per_day
+    {
         return Err("User has voted too many times today".into());
     }
+    if user_is_shadow_banned {
+        // Looks the same to the user, but only the vote itself is stored
+        if let Some(old_shadow_vote) = &old_shadow_vote {
+            if old_shadow_vote.created_at > vote.created_at {
+                return Err("A newer vote already exists".into());
+            }
+        }
+        vote.weight = reputation;
+        info!("Storing vote from shadow banned user [vote={:?}]", vote);
+        let span = dynamodb_span("TransactWriteItems");
+        let result = dynamo_db_client
+            .transact_write_items()
+            .transact_items(put_shadow_vote(&vote, config)?)
+            .transact_items(update_user_daily_votes(
+                &vote.user_id,
+                &today,
+                count_of_votes_today + counts_towards_limit as u32,
+                &recent_votes_received_at,
+                config,
+            ))
+            .return_consumed_capacity(ReturnConsumedCapacity::Total)
+            .send()
+            .instrument(span.clone())
+            .await?;
+        record_consumed_capacity(&span, result.consumed_capacity().unwrap_or_default());
+        return Ok(());
+    }
+    if let Some(old_vote) = &old_vote {
+        // A queued vote can arrive after the user has already voted again online
+        if old_vote.created_at > vote.created_at {
+            return Err("A newer vote already exists".into());
+        }
+    }
+
+    vote.weight = reputation;
+    let vote = &vote;
 
     let mut write_requests: Vec<TransactWriteItem> = vec![];
     if user_does_not_exist {
-        write_requests.push(put_new_user(&vote.user_id, &vote.created_at, config));
+        write_requests.push(put_new_user(
+            &vote.user_id,
+            &now,
+            reputation,
+            recent_votes_received_at,
+            config,
+        )?);
+    } else if !is_replay {
+        write_requests.push(update_user_daily_votes(
+            &vote.user_id,
+            &today,
+            count_of_votes_today + counts_towards_limit as u32,
+            &recent_votes_received_at,
+            config,
+        ));
     }
     if first_vote_on_link_for_user {
-        write_requests.push(put_vote(&vote, config));
-        write_requests.push(update_link_detail(&vote.link, vote.value, config));
-        write_requests.push(increment_link_history(&day, &vote, config));
-        write_requests.push(increment_user_history(&day, &vote, config));
+        write_requests.push(put_vote(vote, config)?);
+        write_requests.push(update_link_detail(vote, sum_of_votes, config));
+        write_requests.push(increment_link_history(&day, vote, config));
+        write_requests.push(increment_user_history(&day, vote, config));
     } else if let Some(old_vote) = old_vote {
         let old_day = old_vote.created_at[..10].to_string();
-        write_requests.push(put_vote(&vote, config));
+        write_requests.push(put_vote(vote, config)?);
         write_requests.push(update_existing_link_detail(
-            &vote.link,
-            -old_vote.value + vote.value, // The change in vote value
+            &old_vote,
+            vote,
+            sum_of_votes.unwrap_or(0),
             config,
         ));
         // If updates are on the same day
         if old_day == day {
             // Update old day
-            write_requests.push(update_link_history(&day, &old_vote, &vote, config));
-            write_requests.push(update_user_history(&day, &old_vote, &vote, config));
+            write_requests.push(update_link_history(&day, &old_vote, vote, config));
+            write_requests.push(update_user_history(&day, &old_vote, vote, config));
         } else {
             // Revert old day, increment new day
             write_requests.push(revert_link_history(&old_vote, &vote.link, config));
             write_requests.push(revert_user_history(&old_vote, &vote.user_id, config));
-            write_requests.push(increment_link_history(&day, &vote, config));
-            write_requests.push(increment_user_history(&day, &vote, config));
+            write_requests.push(increment_link_history(&day, vote, config));
+            write_requests.push(increment_user_history(&day, vote, config));
         }
     }
 
+    let span = dynamodb_span("TransactWriteItems");
     let write_result = dynamo_db_client
         .transact_write_items()
         .set_transact_items(Some(write_requests))
+        .return_consumed_capacity(ReturnConsumedCapacity::Total)
         .send()
+        .instrument(span.clone())
         .await?;
+    record_consumed_capacity(&span, write_result.consumed_capacity().unwrap_or_default());
 
     debug!("Successfully submitted vote [result={:?}]", write_result);
 
-    return Ok(Body::Empty);
+    Ok(())
+}
+
+#[instrument(level = "trace")]
+pub async fn scores(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let Context {
+        request,
+        clock,
+        config,
+        dynamo_db_client,
+        ..
+    } = context;
+    // Extract the links from the body or query parameters and validate them
+    let scores_request = if request.method() == Method::POST {
+        validate_post_scores_request(request.body())?
+    } else {
+        validate_get_scores_request(request.query_string_parameters())?
+    };
+    let link_scores = link_scores(&scores_request, clock, config, dynamo_db_client).await?;
+
+    let mut response =
+        negotiated_response(&link_scores, request.headers(), config.compress_responses)?;
+    add_scores_cache_control(&mut response, config)?;
+    Ok(response)
 }
 
+/// The score of a single link, on every dimension
 #[instrument(level = "trace")]
-pub async fn scores(
-    request: Request,
+pub async fn link(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let Context {
+        request,
+        parameters,
+        clock,
+        config,
+        dynamo_db_client,
+    } = context;
+    let scores_request = ScoresRequest {
+        links: vec![Link::new(&parameters["hostname"])],
+        dimensions: Dimension::ALL.to_vec(),
+    };
+    scores_request.validate()?;
+    let link_score = link_scores(&scores_request, clock, config, dynamo_db_client)
+        .await?
+        .pop()
+        .ok_or("No score")?;
+
+    let mut response =
+        negotiated_response(&link_score, request.headers(), config.compress_responses)?;
+    add_scores_cache_control(&mut response, config)?;
+    Ok(response)
+}
+
+async fn link_scores(
+    scores_request: &ScoresRequest,
+    clock: &dyn Clock,
     config: &Config,
     dynamo_db_client: &Client,
-) -> Result<Body, Error> {
-    // Extract the links from the query parameters and validate them
-    let scores_request = validate_get_scores_request(request.query_string_parameters())?;
-
+) -> Result<Vec<LinkScore>, Error> {
     if config.randomize_scores {
-        let link_scores = random_link_scores(&scores_request.links);
-        let link_scores_json = serde_json::to_string(&link_scores)?;
-        return Ok(link_scores_json.into());
+        return Ok(random_link_scores(
+            &scores_request.links,
+            &config.random_scores,
+        ));
     }
 
-    // Combine the requests for link details into a single DynamoDB request
-    let mut dynamodb_request_builder = KeysAndAttributes::builder();
+    // Get the link details and any overrides, in as few DynamoDB requests as possible
+    let mut keys = vec![];
     for link in &scores_request.links {
-        dynamodb_request_builder = dynamodb_request_builder.keys(HashMap::from([
-            ("PK".to_string(), S(format!("link#{}", link.hostname))),
-            ("SK".to_string(), S(format!("link#{}", link.hostname))),
-        ]));
+        keys.push(get_link_detail(link));
+        keys.push(get_link_override(link));
     }
+    let items = batch_get_items(keys, config, dynamo_db_client).await?;
 
-    // Send the request to DynamoDB and wait for the results
-    let dynamodb_response = dynamo_db_client
-        .batch_get_item()
-        .request_items(&config.table_name, dynamodb_request_builder.build())
-        .send()
-        .await?;
-
-    // Extract the link details
-    //let mut link_details: Vec<LinkDetail> = vec![];
+    // Extract the link details and overrides
     let mut link_details = HashMap::new();
-    for item in dynamodb_response
-        .responses()
-        .ok_or("DynamoDB request error")?
-        .get(&config.table_name)
-        .ok_or("DynamoDB request error")?
-        .iter()
-    {
-        let link_detail = LinkDetail::try_from(item)?;
-        link_detail.validate()?;
-        link_details.insert(link_detail.link.clone(), link_detail);
+    let mut link_overrides = HashMap::new();
+    for item in &items {
+        let entity_type = item
+            .get("entity_type")
+            .ok_or("No entity_type")?
+            .as_s()
+            .or(Err("entity_type is not a string"))?;
+        match entity_type.as_str() {
+            "LinkDetail" => {
+                let link_detail = LinkDetail::try_from(item)?;
+                link_detail.validate()?;
+                link_details.insert(link_detail.link.clone(), link_detail);
+            }
+            "LinkOverride" => {
+                let link_override = LinkOverride::try_from(item)?;
+                link_overrides.insert(link_override.link.clone(), link_override);
+            }
+            _ => {
+                return Err("Unknown entity_type".into());
+            }
+        }
     }
 
+    emit_metrics(
+        &[],
+        &[
+            ("LinksWithDetails", link_details.len() as f64, Unit::Count),
+            (
+                "LinksWithoutDetails",
+                (scores_request.links.len() - link_details.len()) as f64,
+                Unit::Count,
+            ),
+        ],
+    );
+
     // Calculate the scores
-    let link_scores = calculate_link_scores(&scores_request.links, &link_details);
-    let link_scores_json = serde_json::to_string(&link_scores)?;
+    Ok(calculate_link_scores(
+        &scores_request.links,
+        &link_details,
+        &link_overrides,
+        &scores_request.dimensions,
+        config.use_weighted_scores,
+        &clock.now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    ))
+}
+
+fn add_scores_cache_control(response: &mut Response<Body>, config: &Config) -> Result<(), Error> {
+    if config.scores_cache_max_age > 0 {
+        response.headers_mut().insert(
+            CACHE_CONTROL,
+            format!("public, max-age={}", config.scores_cache_max_age).parse()?,
+        );
+    }
+    Ok(())
+}
+
+/// The bad links as a filter list for other tools to subscribe to. Scoring
+/// every link is a scan, so the list is read from the pages that
+/// `compute-filter-lists` writes daily.
+#[instrument(level = "trace")]
+pub async fn filter_list(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let Context {
+        request,
+        config,
+        dynamo_db_client,
+        ..
+    } = context;
+    let format = request
+        .query_string_parameters()
+        .first("format")
+        .ok_or("No format")?
+        .parse::<FilterListFormat>()?;
+
+    let (links, generated_at) = get_filter_list(config, dynamo_db_client).await?;
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(
+            CACHE_CONTROL,
+            format!("public, max-age={}", config.filter_list_cache_max_age),
+        )
+        .header(
+            LAST_MODIFIED,
+            generated_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        )
+        .body(render_filter_list(&links, format, generated_at).into())?)
+}
+
+/// The OpenAPI document for every route above, see `openapi.rs`
+pub async fn openapi() -> Result<Response<Body>, Error> {
+    Ok(Response::new(ApiDoc::openapi().to_json()?.into()))
+}
+
+/// Vote and link counts, read from what `compute-stats` and
+/// `compute-filter-lists` last stored
+#[instrument(level = "trace")]
+pub async fn stats(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let stats = get_stats(context.clock, context.config, context.dynamo_db_client).await?;
+    Ok(Response::builder()
+        .header(
+            CACHE_CONTROL,
+            format!("public, max-age={}", STATS_CACHE_MAX_AGE),
+        )
+        .body(serde_json::to_string(&stats)?.into())?)
+}
+
+/// The version and config the lambda is running with, without touching the table
+#[instrument(level = "trace")]
+pub async fn health(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let health = health::health(context.clock, context.config);
+    Ok(Response::new(serde_json::to_string(&health)?.into()))
+}
+
+/// A 503 when any of the checks fail, so load balancers stop sending requests
+#[instrument(level = "trace")]
+pub async fn ready(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let readiness = readiness(context.config, context.dynamo_db_client).await;
+    let status = match readiness.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header(CACHE_CONTROL, "no-store")
+        .body(serde_json::to_string(&readiness)?.into())?)
+}
+
+/// Move the fake clock to `{"now": "2022-07-28T12:30:00Z"}`, so integration
+/// tests can cross into another day. Only routed when `FAKE_TIME` is set.
+#[instrument(level = "trace")]
+pub async fn set_clock(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let fake_clock = context.clock.fake().ok_or("The clock can't be set")?;
+    let body: serde_json::Value = serde_json::from_slice(context.request.body())?;
+    let now = body["now"].as_str().ok_or("No now")?;
+    fake_clock.set(DateTime::parse_from_rfc3339(now)?.with_timezone(&Utc));
+    info!("Set the fake clock [now={}]", now);
+
+    Ok(Response::new(Body::Empty))
+}
+
+/// Regenerates the filter list now instead of waiting for `compute-filter-lists`,
+/// e.g. after an override. Only for the admin, see `require_admin_key`.
+#[instrument(level = "trace")]
+pub async fn update_filter_list(context: Context<'_>) -> Result<Response<Body>, Error> {
+    let links = filter_lists::update_filter_list(
+        context.clock.now(),
+        context.config,
+        context.dynamo_db_client,
+    )
+    .await?;
+    info!("Updated the filter list [count_of_links={}]", links.len());
 
-    return Ok(link_scores_json.into());
+    Ok(Response::new(
+        json!({ "count_of_links": links.len() }).to_string().into(),
+    ))
 }
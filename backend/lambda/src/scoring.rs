@@ -1,56 +1,337 @@
 use crate::scoring::Score::*;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 
-use crate::types::{database::LinkDetail, *};
+use crate::types::{
+    database::{LinkDetail, LinkOverride},
+    *,
+};
 
-const GOOD_SCORE_BOUND: &i32 = &20;
-const BAD_SCORE_BOUND: &i32 = &-10;
+const GOOD_SCORE_BOUND: f64 = 20.0;
+const BAD_SCORE_BOUND: f64 = -10.0;
 
-pub fn random_link_scores(links: &Vec<Link>) -> Vec<LinkScore> {
-    let score_enums = vec![Good, Bad, Controversial, NoScore];
+/// How the `RANDOMIZE_SCORES` development mode picks scores. Each link's score
+/// comes from the seed and its hostname, so it's the same on every request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RandomScores {
+    pub seed: u64,
+    /// How often each score comes up relative to the others
+    pub weights: Vec<(Score, u32)>,
+}
+impl Default for RandomScores {
+    fn default() -> Self {
+        RandomScores {
+            seed: 0,
+            weights: vec![(Good, 1), (Bad, 1), (Controversial, 1), (NoScore, 1)],
+        }
+    }
+}
+
+/// Weights written like `Good=2,Bad=1,Controversial=1,NoScore=4`, any score
+/// that's left out never comes up
+pub fn parse_random_score_weights(weights: &str) -> Result<Vec<(Score, u32)>, String> {
+    let weights = weights
+        .split(',')
+        .map(|weight| {
+            let (score, weight) = weight
+                .split_once('=')
+                .ok_or(format!("Weight {} should look like Score=1", weight))?;
+            let weight = weight
+                .trim()
+                .parse::<u32>()
+                .or(Err(format!("Weight for {} is not a number", score)))?;
+            Ok((score.trim().parse::<Score>()?, weight))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if weights.iter().map(|(_, weight)| weight).sum::<u32>() == 0 {
+        return Err("At least one weight should be more than 0".to_string());
+    }
+    Ok(weights)
+}
+
+pub fn random_link_scores(links: &Vec<Link>, random_scores: &RandomScores) -> Vec<LinkScore> {
+    let total_weight: u64 = random_scores
+        .weights
+        .iter()
+        .map(|(_, weight)| *weight as u64)
+        .sum();
     let mut scores: Vec<LinkScore> = vec![];
     for link in links {
-        // Choose random score from the enums
-        let random_score = score_enums
-            .get(rand::random::<usize>() % score_enums.len())
-            .unwrap()
-            .to_owned();
+        // Hashed rather than seeding an RNG so the scores don't change between builds
+        let hash = Sha256::new()
+            .chain_update(random_scores.seed.to_be_bytes())
+            .chain_update(link.hostname.as_bytes())
+            .finalize();
+        let mut position = u64::from_be_bytes(hash[..8].try_into().unwrap()) % total_weight;
+        let mut random_score = NoScore;
+        for (score, weight) in &random_scores.weights {
+            if position < *weight as u64 {
+                random_score = score.to_owned();
+                break;
+            }
+            position -= *weight as u64;
+        }
         scores.push(LinkScore::new(link.to_owned(), random_score));
     }
     scores
 }
 
+/// Overrides that haven't expired by `now` take precedence over the votes
 pub fn calculate_link_scores(
     links: &Vec<Link>,
     link_details: &HashMap<Link, LinkDetail>,
+    link_overrides: &HashMap<Link, LinkOverride>,
+    dimensions: &[Dimension],
+    use_weighted_scores: bool,
+    now: &str,
 ) -> Vec<LinkScore> {
     let mut scores: Vec<LinkScore> = vec![];
     for link in links {
-        match link_details.get(&link) {
+        let link_override = link_overrides
+            .get(link)
+            .filter(|link_override| link_override.is_active(now));
+        let link_score = match link_details.get(link) {
             Some(link_detail) => {
-                let LinkDetail {
-                    sum_of_votes,
-                    count_of_votes,
-                    ..
-                } = link_detail;
-
-                if sum_of_votes >= GOOD_SCORE_BOUND {
-                    scores.push(LinkScore::new(link.to_owned(), Score::Good));
-                } else if sum_of_votes <= BAD_SCORE_BOUND {
-                    scores.push(LinkScore::new(link.to_owned(), Score::Bad));
-                } else if count_of_votes > &50
-                    && sum_of_votes > BAD_SCORE_BOUND
-                    && sum_of_votes < GOOD_SCORE_BOUND
-                {
-                    scores.push(LinkScore::new(link.to_owned(), Score::Controversial));
-                } else {
-                    scores.push(LinkScore::new(link.to_owned(), Score::NoScore));
+                let mut dimension_scores = BTreeMap::new();
+                for dimension in dimensions {
+                    let dimension_score = match link_detail.dimensions.get(dimension) {
+                        Some(detail) => score(detail.sum_of_votes as f64, detail.count_of_votes),
+                        None => Score::NoScore,
+                    };
+                    dimension_scores.insert(*dimension, dimension_score);
                 }
+                LinkScore::new(
+                    link.to_owned(),
+                    overall_score(link_detail, use_weighted_scores),
+                )
+                .with_reasons(link_detail.count_of_reasons.clone())
+                .with_dimensions(dimension_scores)
+                .with_moderated(link_detail.frozen_score.is_some())
             }
             None => {
-                scores.push(LinkScore::new(link.to_owned(), Score::NoScore));
+                let dimension_scores = dimensions
+                    .iter()
+                    .map(|dimension| (*dimension, Score::NoScore))
+                    .collect();
+                LinkScore::new(link.to_owned(), Score::NoScore).with_dimensions(dimension_scores)
             }
-        }
+        };
+        scores.push(match link_override {
+            Some(link_override) => link_score
+                .with_score(link_override.score.clone())
+                .with_moderated(true),
+            None => link_score,
+        });
     }
     scores
 }
+
+pub fn overall_score(link_detail: &LinkDetail, use_weighted_scores: bool) -> Score {
+    if let Some(frozen_score) = &link_detail.frozen_score {
+        return frozen_score.clone();
+    }
+    let sum_of_votes = if use_weighted_scores {
+        link_detail.weighted_sum_of_votes
+    } else {
+        link_detail.sum_of_votes as f64
+    };
+    score(sum_of_votes, link_detail.count_of_votes)
+}
+
+// The same rule applies to the overall votes and to each dimension's votes
+fn score(sum_of_votes: f64, count_of_votes: u32) -> Score {
+    if sum_of_votes >= GOOD_SCORE_BOUND {
+        Score::Good
+    } else if sum_of_votes <= BAD_SCORE_BOUND {
+        Score::Bad
+    } else if count_of_votes > 50
+        && sum_of_votes > BAD_SCORE_BOUND
+        && sum_of_votes < GOOD_SCORE_BOUND
+    {
+        Score::Controversial
+    } else {
+        Score::NoScore
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::database::DimensionDetail;
+
+    const NOW: &str = "2023-02-08T12:00:00Z";
+
+    #[test]
+    fn test_random_link_scores() {
+        let links: Vec<Link> = (0..100)
+            .map(|i| Link::new(&format!("example{}.com", i)))
+            .collect();
+        let random_scores = RandomScores::default();
+        // The same on every request, but not the same for every link
+        let scores = random_link_scores(&links, &random_scores);
+        assert_eq!(scores, random_link_scores(&links, &random_scores));
+        assert!(scores.iter().any(|score| *score.score() == Good));
+        assert!(scores.iter().any(|score| *score.score() == Bad));
+        assert_ne!(
+            scores,
+            random_link_scores(
+                &links,
+                &RandomScores {
+                    seed: 1,
+                    ..RandomScores::default()
+                }
+            )
+        );
+
+        let only_bad = RandomScores {
+            seed: 0,
+            weights: parse_random_score_weights("Good=0,Bad=3").unwrap(),
+        };
+        assert!(random_link_scores(&links, &only_bad)
+            .iter()
+            .all(|score| *score.score() == Bad));
+        assert!(parse_random_score_weights("Good=0").is_err());
+        assert!(parse_random_score_weights("Great=1").is_err());
+    }
+
+    fn link_detail(
+        hostname: &str,
+        sum_of_votes: i32,
+        count_of_votes: u32,
+        dimensions: Vec<(Dimension, i32, u32)>,
+    ) -> (Link, LinkDetail) {
+        let link = Link::new(hostname);
+        let link_detail = LinkDetail {
+            link: link.clone(),
+            count_of_votes,
+            sum_of_votes,
+            weighted_sum_of_votes: sum_of_votes as f64 / 2.0,
+            count_of_reasons: BTreeMap::new(),
+            frozen_score: None,
+            created_at: None,
+            count_of_seed_votes: None,
+            dimensions: dimensions
+                .into_iter()
+                .map(|(dimension, sum_of_votes, count_of_votes)| {
+                    (
+                        dimension,
+                        DimensionDetail {
+                            count_of_votes,
+                            sum_of_votes,
+                        },
+                    )
+                })
+                .collect(),
+        };
+        (link, link_detail)
+    }
+
+    #[test]
+    fn test_calculate_link_scores() {
+        let link_details = HashMap::from([
+            link_detail("good.com", 20, 20, vec![]),
+            link_detail("bad.com", -10, 10, vec![]),
+            link_detail("controversial.com", 0, 51, vec![]),
+            link_detail("meh.com", 5, 10, vec![]),
+        ]);
+        let links = [
+            "good.com",
+            "bad.com",
+            "controversial.com",
+            "meh.com",
+            "new.com",
+        ]
+        .into_iter()
+        .map(Link::new)
+        .collect::<Vec<Link>>();
+
+        let scores = calculate_link_scores(&links, &link_details, &HashMap::new(), &[], false, NOW);
+        let expected = [Good, Bad, Controversial, NoScore, NoScore];
+        for ((link, score), link_score) in links.iter().zip(expected).zip(scores) {
+            assert_eq!(link_score, LinkScore::new(link.clone(), score));
+        }
+
+        // The weighted sums are half the raw sums in these fixtures
+        let scores = calculate_link_scores(&links, &link_details, &HashMap::new(), &[], true, NOW);
+        let expected = [NoScore, NoScore, Controversial, NoScore, NoScore];
+        for ((link, score), link_score) in links.iter().zip(expected).zip(scores) {
+            assert_eq!(link_score, LinkScore::new(link.clone(), score));
+        }
+    }
+
+    #[test]
+    fn test_calculate_link_scores_with_dimensions() {
+        let link_details = HashMap::from([link_detail(
+            "clone.com",
+            5,
+            40,
+            vec![
+                (Dimension::ContentQuality, -15, 20),
+                (Dimension::AdIntrusiveness, 20, 20),
+            ],
+        )]);
+        let links = vec![Link::new("clone.com"), Link::new("new.com")];
+        let dimensions = [Dimension::ContentQuality, Dimension::AiGeneration];
+
+        let scores = calculate_link_scores(
+            &links,
+            &link_details,
+            &HashMap::new(),
+            &dimensions,
+            false,
+            NOW,
+        );
+        assert_eq!(
+            scores,
+            vec![
+                // Only the requested dimensions are scored
+                LinkScore::new(Link::new("clone.com"), NoScore).with_dimensions(BTreeMap::from([
+                    (Dimension::ContentQuality, Bad),
+                    (Dimension::AiGeneration, NoScore),
+                ])),
+                LinkScore::new(Link::new("new.com"), NoScore).with_dimensions(BTreeMap::from([
+                    (Dimension::ContentQuality, NoScore),
+                    (Dimension::AiGeneration, NoScore),
+                ])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calculate_link_scores_with_overrides() {
+        let link_details = HashMap::from([
+            link_detail("brigaded.com", 40, 40, vec![]),
+            link_detail("expired.com", 40, 40, vec![]),
+        ]);
+        let link_override = |hostname: &str, score: Score, expires_at: Option<&str>| {
+            (
+                Link::new(hostname),
+                LinkOverride {
+                    link: Link::new(hostname),
+                    score,
+                    reason: "Under investigation".to_string(),
+                    expires_at: expires_at.map(str::to_string),
+                },
+            )
+        };
+        let link_overrides = HashMap::from([
+            link_override("brigaded.com", NoScore, None),
+            link_override("malware.com", Bad, Some("2023-03-01T00:00:00Z")),
+            link_override("expired.com", Bad, Some("2023-02-01T00:00:00Z")),
+        ]);
+        let links = ["brigaded.com", "malware.com", "expired.com"]
+            .into_iter()
+            .map(Link::new)
+            .collect::<Vec<Link>>();
+
+        let scores = calculate_link_scores(&links, &link_details, &link_overrides, &[], false, NOW);
+        assert_eq!(
+            scores,
+            vec![
+                LinkScore::new(Link::new("brigaded.com"), NoScore).with_moderated(true),
+                LinkScore::new(Link::new("malware.com"), Bad).with_moderated(true),
+                LinkScore::new(Link::new("expired.com"), Good),
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::{
+    clock::Clock,
+    dynamodb::*,
+    routes::submit_vote,
+    types::{database::Vote, Config},
+};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, AttributeValue::*},
+    Client,
+};
+use lambda_http::Error;
+use tracing::*;
+use uuid::Uuid;
+
+/// Lift a user's shadow ban and apply the votes they made while shadow banned,
+/// oldest first. They were checked against the daily limit and the settings when
+/// they were received, so only a newer vote or a full ban rejects them now.
+/// Applied votes replace their shadow copies, rejected ones are kept for auditing.
+/// Returns the number of votes that were applied.
+pub async fn lift_shadow_ban(
+    user_id: &Uuid,
+    clock: &dyn Clock,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<u32, Error> {
+    dynamo_db_client
+        .update_item()
+        .table_name(&config.table_name)
+        .set_key(Some(get_user(user_id)))
+        .update_expression("SET is_shadow_banned = :false")
+        .condition_expression("attribute_exists(PK)")
+        .expression_attribute_values(":false", Bool(false))
+        .send()
+        .await?;
+
+    let mut shadow_votes = vec![];
+    for item in batch_get_items(
+        query_shadow_vote_keys(user_id, config, dynamo_db_client).await?,
+        config,
+        dynamo_db_client,
+    )
+    .await?
+    {
+        shadow_votes.push(Vote::try_from(&item)?);
+    }
+    shadow_votes.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+    let mut count_of_applied_votes = 0;
+    for shadow_vote in shadow_votes {
+        let shadow_vote_key = get_shadow_vote(&shadow_vote);
+        match submit_vote(shadow_vote, true, clock, config, dynamo_db_client).await {
+            Ok(()) => {
+                dynamo_db_client
+                    .delete_item()
+                    .table_name(&config.table_name)
+                    .set_key(Some(shadow_vote_key))
+                    .send()
+                    .await?;
+                count_of_applied_votes += 1;
+            }
+            Err(e) => info!("Shadow vote was rejected [error={}]", e),
+        }
+    }
+
+    Ok(count_of_applied_votes)
+}
+
+// The `UserVotes` index doesn't have every attribute, so only the keys are used
+async fn query_shadow_vote_keys(
+    user_id: &Uuid,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<HashMap<String, AttributeValue>>, Error> {
+    let mut keys = vec![];
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .query()
+            .table_name(&config.table_name)
+            .index_name("UserVotes")
+            .key_condition_expression("UserVotes_PK = :user_id")
+            .filter_expression("entity_type = :entity_type")
+            .expression_attribute_values(":user_id", S(user_id.hyphenated().to_string()))
+            .expression_attribute_values(":entity_type", S("ShadowVote".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            keys.push(HashMap::from([
+                ("PK".to_string(), item.get("PK").ok_or("No PK")?.clone()),
+                ("SK".to_string(), item.get("SK").ok_or("No SK")?.clone()),
+            ]));
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok(keys);
+        }
+    }
+}
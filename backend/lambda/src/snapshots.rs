@@ -0,0 +1,444 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::types::Config;
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, AttributeValue::*, PutRequest, WriteRequest},
+    Client,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use lambda_http::Error;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+// Bumped whenever the layout of the snapshot files changes
+const SNAPSHOT_VERSION: u32 = 1;
+// BatchWriteItem is limited to 25 items per request
+const BATCH_WRITE_SIZE: usize = 25;
+
+type Item = HashMap<String, AttributeValue>;
+
+/// The entities in a snapshot, each written to its own file
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SnapshotEntity {
+    LinkDetail,
+    LinkHistory,
+    Vote,
+}
+impl SnapshotEntity {
+    pub const ALL: [SnapshotEntity; 3] = [
+        SnapshotEntity::LinkDetail,
+        SnapshotEntity::LinkHistory,
+        SnapshotEntity::Vote,
+    ];
+
+    pub fn entity_type(&self) -> &'static str {
+        match self {
+            SnapshotEntity::LinkDetail => "LinkDetail",
+            SnapshotEntity::LinkHistory => "LinkHistory",
+            SnapshotEntity::Vote => "Vote",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            SnapshotEntity::LinkDetail => "link_details.ndjson",
+            SnapshotEntity::LinkHistory => "link_histories.ndjson",
+            SnapshotEntity::Vote => "votes.ndjson",
+        }
+    }
+
+    fn from_entity_type(entity_type: &str) -> Option<SnapshotEntity> {
+        SnapshotEntity::ALL
+            .into_iter()
+            .find(|entity| entity.entity_type() == entity_type)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub table_name: String,
+    pub created_at: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub entity_type: String,
+    pub file_name: String,
+    pub count_of_items: u64,
+    /// Hex encoded SHA-256 of the file's contents
+    pub sha256: String,
+}
+
+/// Items are written in the same `{"S": "..."}` form as the AWS CLI, so the
+/// snapshot files can also be used with other DynamoDB tooling
+pub fn item_to_json(item: &Item) -> Result<Value, Error> {
+    let mut object = Map::new();
+    for (name, value) in item {
+        object.insert(name.clone(), attribute_to_json(value)?);
+    }
+    Ok(Value::Object(object))
+}
+
+fn attribute_to_json(value: &AttributeValue) -> Result<Value, Error> {
+    Ok(match value {
+        S(string) => json!({ "S": string }),
+        N(number) => json!({ "N": number }),
+        Bool(boolean) => json!({ "BOOL": boolean }),
+        Null(_) => json!({ "NULL": true }),
+        Ss(strings) => json!({ "SS": strings }),
+        Ns(numbers) => json!({ "NS": numbers }),
+        L(values) => json!({
+            "L": values.iter().map(attribute_to_json).collect::<Result<Vec<_>, _>>()?
+        }),
+        M(item) => json!({ "M": item_to_json(item)? }),
+        _ => return Err(format!("Unsupported attribute value {:?}", value).into()),
+    })
+}
+
+pub fn item_from_json(value: &Value) -> Result<Item, Error> {
+    let object = value.as_object().ok_or("Item is not an object")?;
+    object
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), attribute_from_json(value)?)))
+        .collect()
+}
+
+fn attribute_from_json(value: &Value) -> Result<AttributeValue, Error> {
+    let object = value.as_object().ok_or("Attribute is not an object")?;
+    let (attribute_type, value) = object.iter().next().ok_or("Attribute has no type")?;
+    let strings = |value: &Value| -> Result<Vec<String>, Error> {
+        value
+            .as_array()
+            .ok_or("Set is not an array")?
+            .iter()
+            .map(|value| {
+                Ok(value
+                    .as_str()
+                    .ok_or("Set value is not a string")?
+                    .to_string())
+            })
+            .collect()
+    };
+    Ok(match attribute_type.as_str() {
+        "S" => S(value.as_str().ok_or("S is not a string")?.to_string()),
+        "N" => N(value.as_str().ok_or("N is not a string")?.to_string()),
+        "BOOL" => Bool(value.as_bool().ok_or("BOOL is not a boolean")?),
+        "NULL" => Null(value.as_bool().ok_or("NULL is not a boolean")?),
+        "SS" => Ss(strings(value)?),
+        "NS" => Ns(strings(value)?),
+        "L" => L(value
+            .as_array()
+            .ok_or("L is not an array")?
+            .iter()
+            .map(attribute_from_json)
+            .collect::<Result<_, _>>()?),
+        "M" => M(item_from_json(value)?),
+        _ => return Err(format!("Unsupported attribute type {}", attribute_type).into()),
+    })
+}
+
+/// Replace the user id in a vote with a pseudonym. The same user gets the same
+/// pseudonym throughout a snapshot, so their votes can still be grouped, but
+/// the salt is thrown away afterwards so it can't be traced back to them.
+pub fn anonymise_vote(mut item: Item, salt: &[u8]) -> Result<Item, Error> {
+    let user_id = item
+        .get("UserVotes_PK")
+        .ok_or("No UserVotes_PK")?
+        .as_s()
+        .or(Err("UserVotes_PK is not a string"))?;
+    let user_id = Uuid::parse_str(user_id)?;
+    let hash = Sha256::new()
+        .chain_update(salt)
+        .chain_update(user_id.as_bytes())
+        .finalize();
+    let pseudonym = uuid::Builder::from_random_bytes(hash[..16].try_into()?)
+        .into_uuid()
+        .hyphenated()
+        .to_string();
+    item.insert("SK".to_string(), S(format!("user#{}", pseudonym)));
+    item.insert("UserVotes_PK".to_string(), S(pseudonym));
+    Ok(item)
+}
+
+// Lines are hashed as they're written so the files never need to be read back
+struct SnapshotWriter {
+    writer: BufWriter<File>,
+    hasher: Sha256,
+    count_of_items: u64,
+}
+impl SnapshotWriter {
+    fn create(path: &Path) -> Result<SnapshotWriter, Error> {
+        Ok(SnapshotWriter {
+            writer: BufWriter::new(File::create(path)?),
+            hasher: Sha256::new(),
+            count_of_items: 0,
+        })
+    }
+
+    fn write(&mut self, item: &Item) -> Result<(), Error> {
+        let line = item_to_json(item)?.to_string() + "\n";
+        self.writer.write_all(line.as_bytes())?;
+        self.hasher.update(line.as_bytes());
+        self.count_of_items += 1;
+        Ok(())
+    }
+}
+
+/// Write every `LinkDetail`, `LinkHistory` and anonymised `Vote` in the table
+/// to newline delimited JSON in `directory`, one file per entity type, along
+/// with a manifest of their counts and checksums.
+///
+/// The scan uses strongly consistent reads, but votes that arrive while it's
+/// running may or may not be included.
+pub async fn export_snapshot(
+    directory: &Path,
+    now: DateTime<Utc>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Manifest, Error> {
+    fs::create_dir_all(directory)?;
+    let created_at = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut salt = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    let mut writers = SnapshotEntity::ALL
+        .into_iter()
+        .map(|entity| {
+            SnapshotWriter::create(&directory.join(entity.file_name()))
+                .map(|writer| (entity.entity_type(), writer))
+        })
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    let mut exclusive_start_key: Option<Item> = None;
+    loop {
+        let response = dynamo_db_client
+            .scan()
+            .table_name(&config.table_name)
+            .consistent_read(true)
+            .filter_expression("entity_type IN (:link_detail, :link_history, :vote)")
+            .expression_attribute_values(":link_detail", S("LinkDetail".to_string()))
+            .expression_attribute_values(":link_history", S("LinkHistory".to_string()))
+            .expression_attribute_values(":vote", S("Vote".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            let entity_type = item
+                .get("entity_type")
+                .ok_or("No entity_type")?
+                .as_s()
+                .or(Err("entity_type is not a string"))?;
+            let writer = writers
+                .get_mut(entity_type.as_str())
+                .ok_or(format!("Unexpected entity_type {}", entity_type))?;
+            if entity_type == "Vote" {
+                writer.write(&anonymise_vote(item.clone(), &salt)?)?;
+            } else {
+                writer.write(item)?;
+            }
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+
+    let mut files = vec![];
+    for entity in SnapshotEntity::ALL {
+        let mut writer = writers
+            .remove(entity.entity_type())
+            .ok_or("Missing writer")?;
+        writer.writer.flush()?;
+        files.push(ManifestFile {
+            entity_type: entity.entity_type().to_string(),
+            file_name: entity.file_name().to_string(),
+            count_of_items: writer.count_of_items,
+            sha256: hex::encode(writer.hasher.finalize()),
+        });
+    }
+    let manifest = Manifest {
+        version: SNAPSHOT_VERSION,
+        table_name: config.table_name.clone(),
+        created_at,
+        files,
+    };
+    fs::write(
+        directory.join(MANIFEST_FILE_NAME),
+        serde_json::to_string_pretty(&manifest)? + "\n",
+    )?;
+    Ok(manifest)
+}
+
+/// Check the snapshot in `directory` against its manifest, without writing anything
+pub fn verify_snapshot(directory: &Path) -> Result<Manifest, Error> {
+    let manifest: Manifest =
+        serde_json::from_str(&fs::read_to_string(directory.join(MANIFEST_FILE_NAME))?)?;
+    if manifest.version != SNAPSHOT_VERSION {
+        return Err(format!("Unsupported snapshot version {}", manifest.version).into());
+    }
+    for file in &manifest.files {
+        SnapshotEntity::from_entity_type(&file.entity_type)
+            .ok_or(format!("Unexpected entity_type {}", file.entity_type))?;
+        let contents = fs::read(directory.join(&file.file_name))?;
+        let sha256 = hex::encode(Sha256::digest(&contents));
+        if sha256 != file.sha256 {
+            return Err(format!("Checksum mismatch for {}", file.file_name).into());
+        }
+        let count_of_items = contents.iter().filter(|byte| **byte == b'\n').count() as u64;
+        if count_of_items != file.count_of_items {
+            return Err(format!(
+                "{} has {} items but the manifest says {}",
+                file.file_name, count_of_items, file.count_of_items
+            )
+            .into());
+        }
+    }
+    Ok(manifest)
+}
+
+/// Restore a snapshot made by `export_snapshot`. The snapshot is verified
+/// first, and the table must not already have any of the snapshot's entities.
+pub async fn import_snapshot(
+    directory: &Path,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Manifest, Error> {
+    let manifest = verify_snapshot(directory)?;
+    if has_snapshot_entities(config, dynamo_db_client).await? {
+        return Err(format!("Table {} is not empty", config.table_name).into());
+    }
+
+    for file in &manifest.files {
+        let reader = BufReader::new(File::open(directory.join(&file.file_name))?);
+        let mut items = vec![];
+        for line in reader.lines() {
+            items.push(item_from_json(&serde_json::from_str(&line?)?)?);
+            if items.len() == BATCH_WRITE_SIZE {
+                batch_put_items(std::mem::take(&mut items), config, dynamo_db_client).await?;
+            }
+        }
+        batch_put_items(items, config, dynamo_db_client).await?;
+    }
+    Ok(manifest)
+}
+
+async fn has_snapshot_entities(config: &Config, dynamo_db_client: &Client) -> Result<bool, Error> {
+    let mut exclusive_start_key: Option<Item> = None;
+    loop {
+        let response = dynamo_db_client
+            .scan()
+            .table_name(&config.table_name)
+            .filter_expression("entity_type IN (:link_detail, :link_history, :vote)")
+            .expression_attribute_values(":link_detail", S("LinkDetail".to_string()))
+            .expression_attribute_values(":link_history", S("LinkHistory".to_string()))
+            .expression_attribute_values(":vote", S("Vote".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        if response.count() > 0 {
+            return Ok(true);
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok(false);
+        }
+    }
+}
+
+async fn batch_put_items(
+    items: Vec<Item>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<(), Error> {
+    let mut write_requests: Vec<WriteRequest> = items
+        .into_iter()
+        .map(|item| {
+            WriteRequest::builder()
+                .put_request(PutRequest::builder().set_item(Some(item)).build())
+                .build()
+        })
+        .collect();
+    let mut backoff = Duration::from_millis(50);
+    while !write_requests.is_empty() {
+        let response = dynamo_db_client
+            .batch_write_item()
+            .request_items(&config.table_name, write_requests)
+            .send()
+            .await?;
+        write_requests = response
+            .unprocessed_items()
+            .and_then(|unprocessed| unprocessed.get(&config.table_name))
+            .cloned()
+            .unwrap_or_default();
+        if !write_requests.is_empty() {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(5));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(user_id: &str) -> Item {
+        HashMap::from([
+            ("PK".to_string(), S("link#example.com".to_string())),
+            ("SK".to_string(), S(format!("user#{}", user_id))),
+            ("entity_type".to_string(), S("Vote".to_string())),
+            ("value".to_string(), N("-1".to_string())),
+            ("UserVotes_PK".to_string(), S(user_id.to_string())),
+        ])
+    }
+
+    #[test]
+    fn test_item_json_round_trip() {
+        let item = HashMap::from([
+            ("PK".to_string(), S("link#example.com".to_string())),
+            ("count_of_votes".to_string(), N("3".to_string())),
+            ("is_banned".to_string(), Bool(false)),
+            ("seed_sources".to_string(), Ss(vec!["hosts".to_string()])),
+            (
+                "nested".to_string(),
+                M(HashMap::from([(
+                    "list".to_string(),
+                    L(vec![N("1".to_string()), Null(true)]),
+                )])),
+            ),
+        ]);
+        let json = item_to_json(&item).unwrap();
+        assert_eq!(json["seed_sources"], json!({"SS": ["hosts"]}));
+        assert_eq!(item_from_json(&json).unwrap(), item);
+    }
+
+    #[test]
+    fn test_anonymise_vote() {
+        let user_id = "d0d5d2a6-4c63-4a9e-9e0b-06f1ff3d9d1a";
+        let salt = [7u8; 32];
+        let anonymised = anonymise_vote(vote(user_id), &salt).unwrap();
+        let pseudonym = anonymised["UserVotes_PK"].as_s().unwrap();
+        assert_ne!(pseudonym, user_id);
+        assert_eq!(anonymised["SK"], S(format!("user#{}", pseudonym)));
+        assert_eq!(anonymised["value"], N("-1".to_string()));
+
+        // Stable within a snapshot, different across snapshots
+        assert_eq!(anonymise_vote(vote(user_id), &salt).unwrap(), anonymised);
+        assert_ne!(
+            anonymise_vote(vote(user_id), &[8u8; 32]).unwrap(),
+            anonymised
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use crate::{
+    clock::Clock,
+    dynamodb::{batch_get_items, get_daily_stats, get_link_stats, put_daily_stats, put_link_stats},
+    scoring::calculate_link_scores,
+    types::{
+        api::{DayStats, Stats},
+        database::{DailyStats, LinkDetail, LinkOverride, LinkStats, UserHistory},
+        Config, Link, Score,
+    },
+};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, AttributeValue::*},
+    Client,
+};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use lambda_http::Error;
+use tracing::*;
+
+// Queued votes can land up to a week back, so every day shown is recomputed
+const STATS_DAYS: i64 = 7;
+/// Seconds that clients can cache the stats for, they're only recomputed hourly
+pub const STATS_CACHE_MAX_AGE: u32 = 300;
+
+/// The `STATS_DAYS` days up to and including `now`, oldest first
+pub fn stats_days(now: DateTime<Utc>) -> Vec<String> {
+    (0..STATS_DAYS)
+        .rev()
+        .map(|days_before| {
+            (now - Duration::days(days_before))
+                .format("%Y-%m-%d")
+                .to_string()
+        })
+        .collect()
+}
+
+/// Recomputes the rollup for each of the last `STATS_DAYS` days from the votes
+/// in each day's partition. The link counts are left to `update_link_stats`.
+pub async fn update_daily_stats(
+    now: DateTime<Utc>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<DailyStats>, Error> {
+    let updated_at = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut all_daily_stats = vec![];
+    for day in stats_days(now) {
+        let (count_of_votes, count_of_active_voters) =
+            count_daily_votes(&day, config, dynamo_db_client).await?;
+        let daily_stats = DailyStats {
+            day,
+            count_of_votes,
+            count_of_active_voters,
+            updated_at: updated_at.clone(),
+        };
+        dynamo_db_client
+            .transact_write_items()
+            .transact_items(put_daily_stats(&daily_stats, config)?)
+            .send()
+            .await?;
+        debug!("Updated daily stats [daily_stats={:?}]", daily_stats);
+        all_daily_stats.push(daily_stats);
+    }
+    Ok(all_daily_stats)
+}
+
+/// Stores the link counts from the links scanned by `update_filter_list`, so
+/// that the table is only scanned once a day
+pub async fn update_link_stats(
+    link_details: &HashMap<Link, LinkDetail>,
+    link_overrides: &HashMap<Link, LinkOverride>,
+    now: DateTime<Utc>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<LinkStats, Error> {
+    let link_stats = count_links(
+        link_details,
+        link_overrides,
+        config.use_weighted_scores,
+        now,
+    );
+    dynamo_db_client
+        .transact_write_items()
+        .transact_items(put_link_stats(&link_stats, config)?)
+        .send()
+        .await?;
+    debug!("Updated link stats [link_stats={:?}]", link_stats);
+    Ok(link_stats)
+}
+
+/// Links with votes, and how many of them score each way. Links that only
+/// have votes from blocklist seeds aren't counted, unless they're overridden.
+pub fn count_links(
+    link_details: &HashMap<Link, LinkDetail>,
+    link_overrides: &HashMap<Link, LinkOverride>,
+    use_weighted_scores: bool,
+    now: DateTime<Utc>,
+) -> LinkStats {
+    let mut rated_links: Vec<Link> = link_details
+        .values()
+        .filter(|link_detail| {
+            link_detail.count_of_votes > link_detail.count_of_seed_votes.unwrap_or(0)
+        })
+        .map(|link_detail| link_detail.link.clone())
+        .collect();
+    let updated_at = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut link_stats = LinkStats {
+        count_of_rated_links: rated_links.len() as u32,
+        updated_at: updated_at.clone(),
+        ..Default::default()
+    };
+    rated_links.extend(link_overrides.keys().cloned());
+    rated_links.sort();
+    rated_links.dedup();
+    for link_score in calculate_link_scores(
+        &rated_links,
+        link_details,
+        link_overrides,
+        &[],
+        use_weighted_scores,
+        &updated_at,
+    ) {
+        match link_score.score() {
+            Score::Good => link_stats.count_of_good_links += 1,
+            Score::Bad => link_stats.count_of_bad_links += 1,
+            Score::Controversial => link_stats.count_of_controversial_links += 1,
+            Score::NoScore => {}
+        }
+    }
+    link_stats
+}
+
+// The day's counted votes, and the users that cast them, from its `UserHistory` rows
+async fn count_daily_votes(
+    day: &str,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<(u32, u32), Error> {
+    let mut count_of_votes = 0;
+    let mut count_of_active_voters = 0;
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .query()
+            .table_name(&config.table_name)
+            .key_condition_expression("PK = :day AND begins_with(SK, :user)")
+            .expression_attribute_values(":day", S(format!("day#{}", day)))
+            .expression_attribute_values(":user", S("user#".to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            let user_history = UserHistory::try_from(item)?;
+            // A vote that was changed on a later day moves there, leaving 0 behind
+            if user_history.count_of_votes > 0 {
+                count_of_votes += user_history.count_of_votes;
+                count_of_active_voters += 1;
+            }
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok((count_of_votes, count_of_active_voters));
+        }
+    }
+}
+
+/// Reads the rollups for the last `STATS_DAYS` days and the link counts in a
+/// single batch
+pub async fn get_stats(
+    clock: &dyn Clock,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Stats, Error> {
+    let days = stats_days(clock.now());
+    let mut keys: Vec<HashMap<String, AttributeValue>> =
+        days.iter().map(|day| get_daily_stats(day)).collect();
+    keys.push(get_link_stats());
+    let mut all_daily_stats = vec![];
+    let mut link_stats = None;
+    for item in batch_get_items(keys, config, dynamo_db_client).await? {
+        let entity_type = item
+            .get("entity_type")
+            .ok_or("No entity_type")?
+            .as_s()
+            .or(Err("entity_type is not a string"))?;
+        if entity_type == "LinkStats" {
+            link_stats = Some(LinkStats::try_from(&item)?);
+        } else {
+            all_daily_stats.push(DailyStats::try_from(&item)?);
+        }
+    }
+    Ok(stats_from_daily_stats(
+        &days,
+        &all_daily_stats,
+        link_stats.as_ref(),
+    ))
+}
+
+/// Days without a rollup count as no votes, and there are no link counts until
+/// `compute-filter-lists` has run
+pub fn stats_from_daily_stats(
+    days: &[String],
+    all_daily_stats: &[DailyStats],
+    link_stats: Option<&LinkStats>,
+) -> Stats {
+    let days: Vec<DayStats> = days
+        .iter()
+        .map(|day| {
+            match all_daily_stats
+                .iter()
+                .find(|daily_stats| daily_stats.day == *day)
+            {
+                Some(daily_stats) => DayStats {
+                    day: day.clone(),
+                    count_of_votes: daily_stats.count_of_votes,
+                    count_of_active_voters: daily_stats.count_of_active_voters,
+                },
+                None => DayStats {
+                    day: day.clone(),
+                    count_of_votes: 0,
+                    count_of_active_voters: 0,
+                },
+            }
+        })
+        .collect();
+    let latest = all_daily_stats
+        .iter()
+        .max_by(|a, b| a.updated_at.cmp(&b.updated_at));
+    let link_stats = link_stats.cloned().unwrap_or_default();
+    Stats {
+        count_of_rated_links: link_stats.count_of_rated_links,
+        count_of_good_links: link_stats.count_of_good_links,
+        count_of_bad_links: link_stats.count_of_bad_links,
+        count_of_controversial_links: link_stats.count_of_controversial_links,
+        count_of_votes_today: days.last().map_or(0, |today| today.count_of_votes),
+        count_of_votes_this_week: days.iter().map(|day| day.count_of_votes).sum(),
+        updated_at: latest.map(|latest| latest.updated_at.clone()),
+        days,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn daily_stats(day: &str, count_of_votes: u32, updated_at: &str) -> DailyStats {
+        DailyStats {
+            day: day.to_string(),
+            count_of_votes,
+            count_of_active_voters: count_of_votes / 2,
+            updated_at: updated_at.to_string(),
+        }
+    }
+
+    fn link_detail(
+        hostname: &str,
+        count_of_votes: u32,
+        sum_of_votes: i32,
+        count_of_seed_votes: Option<u32>,
+    ) -> (Link, LinkDetail) {
+        let link = Link::new(hostname);
+        let link_detail = LinkDetail {
+            link: link.clone(),
+            count_of_votes,
+            sum_of_votes,
+            weighted_sum_of_votes: sum_of_votes as f64,
+            count_of_reasons: BTreeMap::new(),
+            dimensions: BTreeMap::new(),
+            frozen_score: None,
+            created_at: None,
+            count_of_seed_votes,
+        };
+        (link, link_detail)
+    }
+
+    #[test]
+    fn test_count_links() {
+        let now = DateTime::parse_from_rfc3339("2023-02-08T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let link_details = HashMap::from([
+            link_detail("good.com", 25, 25, None),
+            link_detail("quiet.com", 2, 1, None),
+            // Only seeded from a blocklist, so no one has rated it
+            link_detail("seeded.com", 10, -10, Some(10)),
+            link_detail("voted-after-seeding.com", 12, -12, Some(10)),
+        ]);
+        assert_eq!(
+            count_links(&link_details, &HashMap::new(), false, now),
+            LinkStats {
+                count_of_rated_links: 3,
+                count_of_good_links: 1,
+                count_of_bad_links: 1,
+                count_of_controversial_links: 0,
+                updated_at: "2023-02-08T00:00:00Z".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_stats_from_daily_stats() {
+        let now = DateTime::parse_from_rfc3339("2023-02-08T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let days = stats_days(now);
+        assert_eq!(days.first().unwrap(), "2023-02-02");
+        assert_eq!(days.last().unwrap(), "2023-02-08");
+
+        let link_stats = LinkStats {
+            count_of_rated_links: 120,
+            ..Default::default()
+        };
+        let stats = stats_from_daily_stats(
+            &days,
+            &[
+                daily_stats("2023-02-03", 10, "2023-02-08T09:00:00Z"),
+                daily_stats("2023-02-08", 6, "2023-02-08T10:00:00Z"),
+            ],
+            Some(&link_stats),
+        );
+        assert_eq!(stats.count_of_votes_today, 6);
+        assert_eq!(stats.count_of_votes_this_week, 16);
+        assert_eq!(stats.count_of_rated_links, 120);
+        assert_eq!(stats.days.len(), 7);
+        assert_eq!(
+            stats.days[1],
+            DayStats {
+                day: "2023-02-03".to_string(),
+                count_of_votes: 10,
+                count_of_active_voters: 5,
+            }
+        );
+        assert_eq!(stats.days[2].count_of_votes, 0);
+        assert_eq!(stats.updated_at.unwrap(), "2023-02-08T10:00:00Z");
+
+        let stats = stats_from_daily_stats(&days, &[], None);
+        assert_eq!(stats.count_of_rated_links, 0);
+        assert_eq!(stats.updated_at, None);
+    }
+}
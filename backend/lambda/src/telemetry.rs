@@ -0,0 +1,154 @@
+use aws_sdk_dynamodb::model::ConsumedCapacity;
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use tracing::{field, info_span, Span};
+use tracing_subscriber::{fmt, fmt::format::FmtSpan};
+
+// Where the metrics show up in CloudWatch
+const NAMESPACE: &str = "Discontent";
+
+/// JSON logs for CloudWatch, or plain text when working locally. Spans are
+/// logged when they close, so DynamoDB calls show up with their timings.
+pub fn init_logging(log_level: &str, log_format: &str) {
+    let subscriber = fmt()
+        .with_env_filter(log_level)
+        .with_span_events(FmtSpan::CLOSE);
+    match log_format {
+        "json" => subscriber
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .without_time()
+            .init(),
+        _ => subscriber.without_time().init(),
+    }
+}
+
+/// A span for one DynamoDB call, the consumed capacity is recorded when it returns
+pub fn dynamodb_span(operation: &'static str) -> Span {
+    info_span!(
+        "dynamodb",
+        operation,
+        consumed_capacity_units = field::Empty
+    )
+}
+
+pub fn record_consumed_capacity<'a>(
+    span: &Span,
+    consumed_capacity: impl IntoIterator<Item = &'a ConsumedCapacity>,
+) {
+    let units: f64 = consumed_capacity
+        .into_iter()
+        .filter_map(|capacity| capacity.capacity_units())
+        .sum();
+    span.record("consumed_capacity_units", units);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Count,
+    Milliseconds,
+}
+impl Unit {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Unit::Count => "Count",
+            Unit::Milliseconds => "Milliseconds",
+        }
+    }
+}
+
+/// A CloudWatch Embedded Metric Format document. CloudWatch picks these out of
+/// the logs, so they're just printed.
+/// https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html
+pub fn metric_document(
+    timestamp: i64,
+    dimensions: &[(&str, &str)],
+    metrics: &[(&str, f64, Unit)],
+) -> Value {
+    let mut document = Map::new();
+    document.insert(
+        "_aws".to_string(),
+        json!({
+            "Timestamp": timestamp,
+            "CloudWatchMetrics": [{
+                "Namespace": NAMESPACE,
+                "Dimensions": [dimensions.iter().map(|(name, _)| name).collect::<Vec<_>>()],
+                "Metrics": metrics
+                    .iter()
+                    .map(|(name, _, unit)| json!({"Name": name, "Unit": unit.as_str()}))
+                    .collect::<Vec<_>>(),
+            }],
+        }),
+    );
+    for (name, value) in dimensions {
+        document.insert(name.to_string(), json!(value));
+    }
+    for (name, value, _) in metrics {
+        document.insert(name.to_string(), json!(value));
+    }
+    Value::Object(document)
+}
+
+pub fn emit_metrics(dimensions: &[(&str, &str)], metrics: &[(&str, f64, Unit)]) {
+    println!(
+        "{}",
+        metric_document(Utc::now().timestamp_millis(), dimensions, metrics)
+    );
+}
+
+/// Keeps the `Reason` dimension to a few known values
+pub fn vote_rejection_reason(error: &str) -> &'static str {
+    match error {
+        "User is banned" => "UserIsBanned",
+        "Voting is disabled" => "VotingIsDisabled",
+        "User has voted too many times today" => "DailyLimitReached",
+        "A newer vote already exists" => "NewerVoteExists",
+        "Vote timestamp is out of range" => "TimestampOutOfRange",
+        _ => "Other",
+    }
+}
+
+pub fn emit_vote_metric<T, E: ToString>(result: &Result<T, E>) {
+    match result {
+        Ok(_) => emit_metrics(&[], &[("VotesAccepted", 1.0, Unit::Count)]),
+        Err(e) => emit_metrics(
+            &[("Reason", vote_rejection_reason(&e.to_string()))],
+            &[("VotesRejected", 1.0, Unit::Count)],
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric_document() {
+        let document = metric_document(
+            1658925000000,
+            &[("Route", "/v1/scores")],
+            &[("Latency", 12.0, Unit::Milliseconds)],
+        );
+        assert_eq!(
+            document,
+            json!({
+                "_aws": {
+                    "Timestamp": 1658925000000i64,
+                    "CloudWatchMetrics": [{
+                        "Namespace": "Discontent",
+                        "Dimensions": [["Route"]],
+                        "Metrics": [{"Name": "Latency", "Unit": "Milliseconds"}],
+                    }],
+                },
+                "Route": "/v1/scores",
+                "Latency": 12.0,
+            })
+        );
+        assert_eq!(vote_rejection_reason("User is banned"), "UserIsBanned");
+        assert_eq!(
+            vote_rejection_reason("value: Vote should be -1 or 1"),
+            "Other"
+        );
+    }
+}
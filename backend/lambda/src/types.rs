@@ -1,16 +1,42 @@
-use crate::validate::*;
+use crate::{config::AdminKey, scoring::RandomScores, validate::*};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr};
+use utoipa::ToSchema;
 use validator::Validate;
 
+/// Loaded and validated by `config::load_config`
 #[derive(Debug)]
 pub struct Config {
     pub table_name: String,
+    pub log_level: String,
+    /// `json` for CloudWatch, or `text` to read locally
+    pub log_format: String,
+    /// Score links on the reputation weighted sum of votes instead of the raw sum
+    pub use_weighted_scores: bool,
+    /// Origins allowed to call the API, `*` for any
+    pub cors_allowed_origins: Vec<String>,
+    /// Seconds that clients can cache scores for, no caching when 0
+    pub scores_cache_max_age: u32,
+    pub filter_list_cache_max_age: u32,
+    /// Compress JSON responses in the lambda, for when it's not behind API Gateway
+    pub compress_responses: bool,
+    /// Queued votes older than this are dropped rather than counted against an old day
+    pub maximum_queued_vote_age_in_days: u32,
+    /// For the admin only endpoints, which are off without it
+    pub admin_key: Option<AdminKey>,
+    /// Requests per minute from each IP to the rate limited routes, unlimited when 0
+    pub rate_limit_per_minute: u32,
     pub use_local_database: bool,
     pub randomize_scores: bool,
-    pub use_system_time: bool,
+    pub random_scores: RandomScores,
+    /// Start a fake clock here instead of using the system time
+    pub fake_time: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[derive(
+    Debug, Validate, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, ToSchema,
+)]
 pub struct Link {
     #[validate(custom = "is_hostname_valid")]
     pub hostname: String,
@@ -23,242 +49,997 @@ impl Link {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// `NoScore` is part of the API, so it keeps the enum name in it
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 pub enum Score {
     Good,
     Bad,
     Controversial,
     NoScore,
 }
+impl Score {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Score::Good => "Good",
+            Score::Bad => "Bad",
+            Score::Controversial => "Controversial",
+            Score::NoScore => "NoScore",
+        }
+    }
+}
+impl FromStr for Score {
+    type Err = String;
+    fn from_str(score: &str) -> Result<Self, Self::Err> {
+        [
+            Score::Good,
+            Score::Bad,
+            Score::Controversial,
+            Score::NoScore,
+        ]
+        .into_iter()
+        .find(|known_score| known_score.as_str() == score)
+        .ok_or(format!("Unknown score {}", score))
+    }
+}
+
+/// Why a link was downvoted
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ToSchema,
+)]
+pub enum VoteReason {
+    AiGenerated,
+    SeoSpam,
+    ScraperClone,
+    Paywalled,
+    Malware,
+}
+impl VoteReason {
+    pub const ALL: [VoteReason; 5] = [
+        VoteReason::AiGenerated,
+        VoteReason::SeoSpam,
+        VoteReason::ScraperClone,
+        VoteReason::Paywalled,
+        VoteReason::Malware,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VoteReason::AiGenerated => "AiGenerated",
+            VoteReason::SeoSpam => "SeoSpam",
+            VoteReason::ScraperClone => "ScraperClone",
+            VoteReason::Paywalled => "Paywalled",
+            VoteReason::Malware => "Malware",
+        }
+    }
+
+    /// The `LinkDetail` attribute that counts votes with this reason
+    pub fn count_attribute(&self) -> &'static str {
+        match self {
+            VoteReason::AiGenerated => "count_of_ai_generated_votes",
+            VoteReason::SeoSpam => "count_of_seo_spam_votes",
+            VoteReason::ScraperClone => "count_of_scraper_clone_votes",
+            VoteReason::Paywalled => "count_of_paywalled_votes",
+            VoteReason::Malware => "count_of_malware_votes",
+        }
+    }
+}
+impl FromStr for VoteReason {
+    type Err = String;
+    fn from_str(reason: &str) -> Result<Self, Self::Err> {
+        VoteReason::ALL
+            .into_iter()
+            .find(|vote_reason| vote_reason.as_str() == reason)
+            .ok_or(format!("Unknown vote reason {}", reason))
+    }
+}
+
+/// An aspect of a site that can be voted on separately from the overall score
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ToSchema,
+)]
+pub enum Dimension {
+    ContentQuality,
+    AdIntrusiveness,
+    AiGeneration,
+}
+impl Dimension {
+    pub const ALL: [Dimension; 3] = [
+        Dimension::ContentQuality,
+        Dimension::AdIntrusiveness,
+        Dimension::AiGeneration,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Dimension::ContentQuality => "ContentQuality",
+            Dimension::AdIntrusiveness => "AdIntrusiveness",
+            Dimension::AiGeneration => "AiGeneration",
+        }
+    }
+
+    /// The `LinkDetail` attributes that hold this dimension's aggregates
+    pub fn count_attribute(&self) -> &'static str {
+        match self {
+            Dimension::ContentQuality => "count_of_content_quality_votes",
+            Dimension::AdIntrusiveness => "count_of_ad_intrusiveness_votes",
+            Dimension::AiGeneration => "count_of_ai_generation_votes",
+        }
+    }
+
+    pub fn sum_attribute(&self) -> &'static str {
+        match self {
+            Dimension::ContentQuality => "sum_of_content_quality_votes",
+            Dimension::AdIntrusiveness => "sum_of_ad_intrusiveness_votes",
+            Dimension::AiGeneration => "sum_of_ai_generation_votes",
+        }
+    }
+}
+impl FromStr for Dimension {
+    type Err = String;
+    fn from_str(dimension: &str) -> Result<Self, Self::Err> {
+        Dimension::ALL
+            .into_iter()
+            .find(|known_dimension| known_dimension.as_str() == dimension)
+            .ok_or(format!("Unknown dimension {}", dimension))
+    }
+}
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Validate, Serialize, Deserialize, PartialEq, Clone, ToSchema)]
 pub struct LinkScore {
     #[validate]
     link: Link,
     score: Score,
+    /// Only the reasons that have at least one vote
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    reasons: BTreeMap<VoteReason, u32>,
+    /// Only the dimensions the client asked for
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    dimensions: BTreeMap<Dimension, Score>,
+    /// Set when the score comes from a moderator rather than the votes
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    moderated: bool,
 }
 impl LinkScore {
     pub fn new(link: Link, score: Score) -> Self {
-        LinkScore { link, score }
+        LinkScore {
+            link,
+            score,
+            reasons: BTreeMap::new(),
+            dimensions: BTreeMap::new(),
+            moderated: false,
+        }
+    }
+
+    pub fn link(&self) -> &Link {
+        &self.link
+    }
+
+    pub fn score(&self) -> &Score {
+        &self.score
+    }
+
+    pub fn with_score(mut self, score: Score) -> Self {
+        self.score = score;
+        self
+    }
+
+    pub fn with_moderated(mut self, moderated: bool) -> Self {
+        self.moderated = moderated;
+        self
+    }
+
+    pub fn with_reasons(mut self, reasons: BTreeMap<VoteReason, u32>) -> Self {
+        self.reasons = reasons;
+        self
+    }
+
+    pub fn with_dimensions(mut self, dimensions: BTreeMap<Dimension, Score>) -> Self {
+        self.dimensions = dimensions;
+        self
     }
 }
 
 pub mod api {
-    use super::{Link, LinkScore};
-    use crate::validate::is_vote_value_valid;
+    use super::{Dimension, Link, VoteReason};
+    use crate::validate::{
+        is_timestamp_valid, is_vote_value_valid, validate_queued_vote_request_reason,
+        validate_vote_request_reason,
+    };
     use serde::{Deserialize, Serialize};
+    use utoipa::ToSchema;
     use uuid::Uuid;
     use validator::Validate;
 
-    #[derive(Debug, Validate, Deserialize, PartialEq)]
+    #[derive(Debug, Validate, Deserialize, PartialEq, ToSchema)]
+    #[validate(schema(function = "validate_vote_request_reason"))]
     pub struct VoteRequest {
         #[validate]
         pub link: Link,
         #[validate(custom = "is_vote_value_valid")]
         pub value: i32,
         pub user_id: Uuid,
+        #[serde(default)]
+        pub reason: Option<VoteReason>,
+        #[serde(default)]
+        pub dimension: Option<Dimension>,
     }
 
-    #[derive(Debug, Validate, Deserialize, Serialize, PartialEq)]
-    pub struct ScoresRequest {
+    #[derive(Debug, Validate, Deserialize, Serialize, PartialEq, ToSchema)]
+    #[validate(schema(function = "validate_queued_vote_request_reason"))]
+    pub struct QueuedVoteRequest {
         #[validate]
-        #[validate(length(min = 1, max = 100))]
-        pub links: Vec<Link>,
+        pub link: Link,
+        #[validate(custom = "is_vote_value_valid")]
+        pub value: i32,
+        pub user_id: Uuid,
+        #[serde(default)]
+        pub reason: Option<VoteReason>,
+        #[serde(default)]
+        pub dimension: Option<Dimension>,
+        /// When the vote was made on the client, not when it was sent
+        #[validate(custom = "is_timestamp_valid")]
+        pub created_at: String,
+    }
+
+    #[derive(Debug, Validate, Deserialize, Serialize, PartialEq, ToSchema)]
+    pub struct VotesRequest {
+        #[validate]
+        #[validate(length(min = 1, max = 20))]
+        pub votes: Vec<QueuedVoteRequest>,
     }
 
-    #[derive(Debug, Validate, Serialize)]
-    pub struct ScoresResponse {
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, ToSchema)]
+    pub struct VoteResult {
+        pub success: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+    impl VoteResult {
+        pub fn success() -> Self {
+            VoteResult {
+                success: true,
+                error: None,
+            }
+        }
+
+        pub fn failure(error: String) -> Self {
+            VoteResult {
+                success: false,
+                error: Some(error),
+            }
+        }
+    }
+
+    #[derive(Debug, Validate, Deserialize, Serialize, PartialEq, ToSchema)]
+    pub struct ScoresRequest {
         #[validate]
         #[validate(length(min = 1, max = 100))]
-        scores: Vec<LinkScore>,
+        pub links: Vec<Link>,
+        /// Dimensions to score on top of the overall score
+        #[serde(default)]
+        #[validate(length(max = 3))]
+        pub dimensions: Vec<Dimension>,
     }
 
-    #[derive(Debug, Serialize)]
+    /// The body of every error response
+    #[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
     pub struct Error {
         pub error: String,
-        pub description: serde_json::Value,
+    }
+
+    /// How the dataset is growing, from the daily rollups
+    #[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+    pub struct Stats {
+        /// Links with at least one vote from a user, as of the daily scan
+        pub count_of_rated_links: u32,
+        pub count_of_good_links: u32,
+        pub count_of_bad_links: u32,
+        pub count_of_controversial_links: u32,
+        pub count_of_votes_today: u32,
+        pub count_of_votes_this_week: u32,
+        /// The last 7 days, oldest first, including days without any votes
+        pub days: Vec<DayStats>,
+        /// When the rollups were last computed, if ever
+        pub updated_at: Option<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, ToSchema)]
+    pub struct DayStats {
+        pub day: String,
+        pub count_of_votes: u32,
+        pub count_of_active_voters: u32,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+    pub struct Health {
+        /// The version in `Cargo.toml` the lambda was built from
+        pub version: String,
+        pub config: ConfigSummary,
+    }
+
+    /// The settings that change how the API behaves, without any secrets
+    #[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+    pub struct ConfigSummary {
+        pub table_name: String,
+        pub use_weighted_scores: bool,
+        pub cors_allowed_origins: Vec<String>,
+        pub admin_routes_are_enabled: bool,
+        pub use_local_database: bool,
+        pub randomize_scores: bool,
+        pub clock_is_fake: bool,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+    pub struct Readiness {
+        pub ready: bool,
+        pub checks: Vec<Check>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, ToSchema)]
+    pub struct Check {
+        pub name: String,
+        pub ok: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
     }
 }
 
 pub mod database {
-    use super::Link;
+    use super::{Dimension, Link, Score, VoteReason};
+    use crate::ban_rules::BanRuleConfig;
+    use crate::migrations::migrate_item;
     use crate::validate::*;
     use aws_sdk_dynamodb::model::AttributeValue;
     use lambda_http::Error;
-    use serde::Deserialize;
-    use std::collections::HashMap;
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+    use std::collections::{BTreeMap, HashMap};
     use uuid::Uuid;
     use validator::Validate;
     // TODO: Add validation to these database types
 
-    #[derive(Debug, Validate, Deserialize, PartialEq)]
+    /// Bumped along with each migration in `migrations.rs`. Items from before
+    /// versioning have no `schema_version`, which is version 0.
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    /// Items from a newer version may have changed shape in ways this code
+    /// doesn't know about, so they're rejected rather than misread
+    pub fn schema_version(hash_map: &HashMap<String, AttributeValue>) -> Result<u32, Error> {
+        let schema_version = match hash_map.get("schema_version") {
+            Some(schema_version) => schema_version
+                .as_n()
+                .or(Err("schema_version is not a number"))?
+                .parse::<u32>()?,
+            None => 0,
+        };
+        if schema_version > SCHEMA_VERSION {
+            return Err(format!("Unsupported schema_version {}", schema_version).into());
+        }
+        Ok(schema_version)
+    }
+
+    /// Decode an entity from an item. Items from an older schema version are run
+    /// through the migrations first, so entities only describe the latest shape.
+    pub fn from_item<T: DeserializeOwned>(
+        hash_map: &HashMap<String, AttributeValue>,
+    ) -> Result<T, Error> {
+        let entity = match migrate_item(hash_map)? {
+            Some(migrated) => serde_dynamo::from_item(migrated)?,
+            None => serde_dynamo::from_item(hash_map.clone())?,
+        };
+        Ok(entity)
+    }
+
+    /// Encode an entity as a whole item, stamped with its `entity_type` and the
+    /// current `schema_version`
+    pub fn to_item<T: Serialize>(
+        entity: &T,
+        entity_type: &str,
+    ) -> Result<HashMap<String, AttributeValue>, Error> {
+        let mut item: HashMap<String, AttributeValue> = serde_dynamo::to_item(entity)?;
+        item.insert(
+            "entity_type".to_string(),
+            AttributeValue::S(entity_type.to_string()),
+        );
+        item.insert(
+            "schema_version".to_string(),
+            AttributeValue::N(SCHEMA_VERSION.to_string()),
+        );
+        Ok(item)
+    }
+
+    macro_rules! impl_try_from_item {
+        ($($entity:ty),*) => {
+            $(
+                impl TryFrom<&HashMap<String, AttributeValue>> for $entity {
+                    type Error = Error;
+                    fn try_from(hash_map: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
+                        from_item(hash_map)
+                    }
+                }
+            )*
+        };
+    }
+    impl_try_from_item!(
+        UserHistory,
+        User,
+        Settings,
+        LinkDetail,
+        LinkOverride,
+        UserVote,
+        LinkHistory,
+        MigrationProgress,
+        DailyStats,
+        LinkStats,
+        FilterListPage
+    );
+
+    /// Composite keys like `link#example.com`. Only the part after the first `#`
+    /// is decoded, so `user_id` reads the same from `user#<id>` and `shadow#<id>`.
+    mod keys {
+        use serde::{de::Error, Deserialize, Deserializer};
+
+        fn suffix<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+            let key = String::deserialize(deserializer)?;
+            match key.split_once('#') {
+                Some((_, suffix)) => Ok(suffix.to_string()),
+                None => Err(D::Error::custom(format!("Malformed key {}", key))),
+            }
+        }
+
+        pub mod link {
+            use super::super::Link;
+            use serde::{Deserializer, Serializer};
+
+            pub fn serialize<S: Serializer>(link: &Link, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&format!("link#{}", link.hostname))
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Link, D::Error> {
+                Ok(Link::new(&super::suffix(deserializer)?))
+            }
+        }
+
+        pub mod user {
+            use super::super::Uuid;
+            use serde::{de::Error, Deserializer, Serializer};
+
+            pub fn serialize<S: Serializer>(
+                user_id: &Uuid,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&format!("user#{}", user_id.hyphenated()))
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Uuid, D::Error> {
+                Uuid::parse_str(&super::suffix(deserializer)?).map_err(D::Error::custom)
+            }
+        }
+
+        pub mod day {
+            use serde::{Deserializer, Serializer};
+
+            pub fn serialize<S: Serializer>(day: &str, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&format!("day#{}", day))
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<String, D::Error> {
+                super::suffix(deserializer)
+            }
+        }
+
+        pub mod page {
+            use serde::{de::Error, Deserializer, Serializer};
+
+            // Zero padded so the pages sort in order
+            pub fn serialize<S: Serializer>(page: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&format!("page#{:04}", page))
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<u32, D::Error> {
+                super::suffix(deserializer)?
+                    .parse()
+                    .map_err(D::Error::custom)
+            }
+        }
+    }
+
+    // Settings are edited by hand in the console, where a JSON string is easier
+    // to work with than nested DynamoDB maps
+    mod json_string {
+        use serde::{
+            de::DeserializeOwned, de::Error, Deserialize, Deserializer, Serialize, Serializer,
+        };
+
+        pub fn serialize<T: Serialize, S: Serializer>(
+            value: &T,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            let json = serde_json::to_string(value).map_err(serde::ser::Error::custom)?;
+            serializer.serialize_str(&json)
+        }
+
+        pub fn deserialize<'de, T: DeserializeOwned, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<T, D::Error> {
+            serde_json::from_str(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+        }
+    }
+
+    // Each reason's count is its own attribute so it can be incremented on its own
+    mod reason_counts {
+        use super::VoteReason;
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+        use std::collections::{BTreeMap, HashMap};
+
+        pub fn serialize<S: Serializer>(
+            count_of_reasons: &BTreeMap<VoteReason, u32>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(
+                count_of_reasons
+                    .iter()
+                    .map(|(reason, count)| (reason.count_attribute(), count)),
+            )
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<BTreeMap<VoteReason, u32>, D::Error> {
+            let attributes = HashMap::<String, serde_json::Value>::deserialize(deserializer)?;
+            let mut count_of_reasons = BTreeMap::new();
+            for reason in VoteReason::ALL {
+                if let Some(count) = attributes.get(reason.count_attribute()) {
+                    let count = count
+                        .as_u64()
+                        .ok_or_else(|| D::Error::custom("reason count is not a number"))?;
+                    if count > 0 {
+                        count_of_reasons.insert(reason, count as u32);
+                    }
+                }
+            }
+            Ok(count_of_reasons)
+        }
+    }
+
+    // Same for the dimensions, which have a count and a sum each
+    mod dimension_details {
+        use super::{Dimension, DimensionDetail};
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+        use std::collections::{BTreeMap, HashMap};
+
+        pub fn serialize<S: Serializer>(
+            dimensions: &BTreeMap<Dimension, DimensionDetail>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(dimensions.iter().flat_map(|(dimension, detail)| {
+                [
+                    (dimension.count_attribute(), detail.count_of_votes as i64),
+                    (dimension.sum_attribute(), detail.sum_of_votes as i64),
+                ]
+            }))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<BTreeMap<Dimension, DimensionDetail>, D::Error> {
+            let attributes = HashMap::<String, serde_json::Value>::deserialize(deserializer)?;
+            let mut dimensions = BTreeMap::new();
+            for dimension in Dimension::ALL {
+                let count = attributes.get(dimension.count_attribute());
+                let sum = attributes.get(dimension.sum_attribute());
+                if let (Some(count), Some(sum)) = (count, sum) {
+                    let dimension_detail = DimensionDetail {
+                        count_of_votes: count
+                            .as_u64()
+                            .ok_or_else(|| D::Error::custom("dimension count is not a number"))?
+                            as u32,
+                        sum_of_votes: sum
+                            .as_i64()
+                            .ok_or_else(|| D::Error::custom("dimension sum is not a number"))?
+                            as i32,
+                    };
+                    if dimension_detail.count_of_votes > 0 {
+                        dimensions.insert(dimension, dimension_detail);
+                    }
+                }
+            }
+            Ok(dimensions)
+        }
+    }
+
+    #[derive(Debug, Validate, Serialize, Deserialize, PartialEq)]
     pub struct Vote {
         #[validate]
+        #[serde(rename = "PK", with = "keys::link")]
         pub link: Link,
         #[validate(custom = "is_vote_value_valid")]
         pub value: i32,
+        #[serde(rename = "SK", with = "keys::user")]
         pub user_id: Uuid,
         #[validate(custom = "is_timestamp_valid")]
-        pub created_at: String
+        pub created_at: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reason: Option<VoteReason>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub dimension: Option<Dimension>,
+        /// The user's reputation when the vote was made
+        pub weight: f64,
     }
     impl TryFrom<&HashMap<String, AttributeValue>> for Vote {
         type Error = Error;
         fn try_from(hash_map: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
-            let primary_key = hash_map
-                .get("PK")
-                .ok_or("No PK")?
-                .as_s()
-                .or(Err("PK is not a string"))?;
-            let link = Link::new(primary_key.split('#').nth(1).ok_or("No link")?);
-            let value = hash_map
-                .get("value")
-                .ok_or("No value")?
-                .as_n()
-                .or(Err("value is not a number"))?
-                .parse::<i32>()?;
-            let sort_key = hash_map
-                .get("SK")
-                .ok_or("No SK")?
-                .as_s()
-                .or(Err("SK is not a string"))?;
-            let user_id = Uuid::parse_str(sort_key.split('#').nth(1).ok_or("No user_id")?)?;
-            let created_at = hash_map
-                .get("created_at")
-                .ok_or("No created_at")?
-                .as_s()
-                .or(Err("created_at is not a string"))?
-                .to_string();
-            let vote = Vote {
-                link,
-                value,
-                user_id,
-                created_at,
-            };
+            let vote: Vote = from_item(hash_map)?;
             vote.validate()?;
             Ok(vote)
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
     pub struct UserHistory {
+        #[serde(rename = "PK", with = "keys::day")]
         pub day: String,
         pub count_of_votes: u32,
         pub sum_of_votes: i32,
     }
-    impl TryFrom<&HashMap<String, AttributeValue>> for UserHistory {
-        type Error = Error;
-        fn try_from(hash_map: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
-            let primary_key = hash_map
-                .get("PK")
-                .ok_or("No PK")?
-                .as_s()
-                .or(Err("PK is not a string"))?;
-            let day = primary_key
-                .split('#')
-                .nth(1)
-                .ok_or("No day found in PK")?
-                .to_string();
-            let count_of_votes = hash_map
-                .get("count_of_votes")
-                .ok_or("No count_of_votes")?
-                .as_n()
-                .or(Err("count_of_votes is not a number"))?
-                .parse::<u32>()?;
-            let sum_of_votes = hash_map
-                .get("sum_of_votes")
-                .ok_or("No sum_of_votes")?
-                .as_n()
-                .or(Err("sum_of_votes is not a number"))?
-                .parse::<i32>()?;
-
-            Ok(UserHistory {
-                day,
-                count_of_votes,
-                sum_of_votes,
-            })
-        }
-    }
 
-    #[derive(Debug)]
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
     pub struct User {
         pub is_banned: bool,
-    }
-    impl TryFrom<&HashMap<String, AttributeValue>> for User {
-        type Error = Error;
-        fn try_from(hash_map: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
-            let is_banned = hash_map
-                .get("is_banned")
-                .ok_or("No is_banned")?
-                .as_bool()
-                .or(Err("is_banned is not a bool"))?
-                .clone();
-
-            Ok(User { is_banned })
-        }
+        /// Votes are accepted as usual, but don't count towards anything
+        #[serde(default)]
+        pub is_shadow_banned: bool,
+        pub created_at: String,
+        /// How much each of the user's votes counts towards `weighted_sum_of_votes`
+        pub reputation: f64,
+        #[serde(default)]
+        pub count_of_bans: u32,
+        /// The server's day that `count_of_votes_on_last_vote_day` is for
+        #[serde(default)]
+        pub last_vote_day: String,
+        /// First votes received that day, whichever day they were cast, for the daily limit
+        #[serde(default)]
+        pub count_of_votes_on_last_vote_day: u32,
+        /// When the server received the user's latest votes, for the `VoteRate` rules
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub recent_votes_received_at: Vec<String>,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
     pub struct Settings {
         pub voting_is_disabled: bool,
         pub maximum_votes_per_user_per_day: u32,
-    }
-    impl TryFrom<&HashMap<String, AttributeValue>> for Settings {
-        type Error = Error;
-        fn try_from(hash_map: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
-            let voting_is_disabled = hash_map
-                .get("voting_is_disabled")
-                .ok_or("No voting_is_disabled")?
-                .as_bool()
-                .or(Err("voting_is_disabled is not a bool"))?
-                .clone();
-            let maximum_votes_per_user_per_day = hash_map
-                .get("maximum_votes_per_user_per_day")
-                .ok_or("No maximum_votes_per_user_per_day")?
-                .as_n()
-                .or(Err("maximum_votes_per_user_per_day is not a number"))?
-                .parse::<u32>()?;
-
-            Ok(Settings {
-                voting_is_disabled,
-                maximum_votes_per_user_per_day,
-            })
-        }
+        /// Hold the score of links flagged for a vote burst until they're reviewed
+        #[serde(default)]
+        pub freeze_suspicious_links: bool,
+        /// Stored as a JSON document, see `ban_rules.rs` for the format
+        #[serde(default, with = "json_string")]
+        pub ban_rules: Vec<BanRuleConfig>,
     }
 
-    #[derive(Debug, Validate, PartialEq)]
+    #[derive(Debug, Validate, Serialize, Deserialize, PartialEq)]
     pub struct LinkDetail {
         #[validate]
+        #[serde(rename = "PK", with = "keys::link")]
         pub link: super::Link,
         pub count_of_votes: u32,
         pub sum_of_votes: i32,
+        /// Each vote multiplied by the voter's reputation at the time
+        pub weighted_sum_of_votes: f64,
+        /// Only the reasons that have at least one vote
+        #[serde(flatten, with = "reason_counts")]
+        pub count_of_reasons: BTreeMap<VoteReason, u32>,
+        /// Only the dimensions that have been voted on
+        #[serde(flatten, with = "dimension_details")]
+        pub dimensions: BTreeMap<Dimension, DimensionDetail>,
+        /// Set while a suspected vote burst is being reviewed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub frozen_score: Option<Score>,
+        /// When the link got its first vote, if it was after this was recorded
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub created_at: Option<String>,
+        /// The part of `count_of_votes` from blocklist seeds, see `blocklists.rs`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub count_of_seed_votes: Option<u32>,
     }
-    impl TryFrom<&HashMap<String, AttributeValue>> for LinkDetail {
-        type Error = Error;
-        fn try_from(hash_map: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
-            let primary_key = hash_map
-                .get("PK")
-                .ok_or("No PK")?
-                .as_s()
-                .or(Err("PK is not a string"))?;
-            let link = Link::new(primary_key.split('#').nth(1).ok_or("No link")?);
-            let count_of_votes = hash_map
-                .get("count_of_votes")
-                .ok_or("No count_of_votes")?
-                .as_n()
-                .or(Err("count_of_votes is not a number"))?
-                .parse::<u32>()?;
-            let sum_of_votes = hash_map
-                .get("sum_of_votes")
-                .ok_or("No sum_of_votes")?
-                .as_n()
-                .or(Err("sum_of_votes is not a number"))?
-                .parse::<i32>()?;
-
-            Ok(LinkDetail {
-                link,
-                count_of_votes,
-                sum_of_votes,
-            })
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub struct DimensionDetail {
+        pub count_of_votes: u32,
+        pub sum_of_votes: i32,
+    }
+
+    /// A score forced by a moderator, whatever the votes say
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    pub struct LinkOverride {
+        #[serde(rename = "PK", with = "keys::link")]
+        pub link: Link,
+        pub score: Score,
+        pub reason: String,
+        /// Overrides without an expiry last until they're deleted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expires_at: Option<String>,
+    }
+    impl LinkOverride {
+        pub fn is_active(&self, now: &str) -> bool {
+            self.expires_at
+                .as_ref()
+                .is_none_or(|expires_at| expires_at.as_str() > now)
+        }
+    }
+
+    /// How far a run of `migrations.rs` has got, so an interrupted run can carry on
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    pub struct MigrationProgress {
+        pub target_version: u32,
+        /// The scan's `LastEvaluatedKey` as JSON, so the next page can be picked up
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_evaluated_key: Option<String>,
+        pub count_of_scanned_items: u64,
+        pub count_of_migrated_items: u64,
+        pub started_at: String,
+        pub updated_at: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub completed_at: Option<String>,
+    }
+
+    /// A day's totals, written by `compute-stats` so that `GET /v1/stats` doesn't
+    /// have to scan
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    pub struct DailyStats {
+        #[serde(rename = "SK", with = "keys::day")]
+        pub day: String,
+        pub count_of_votes: u32,
+        pub count_of_active_voters: u32,
+        pub updated_at: String,
+    }
+
+    /// The link counts for the whole table as of `updated_at`. Scoring every link
+    /// is a scan, so they're written daily by `compute-filter-lists` along with
+    /// the filter list.
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+    pub struct LinkStats {
+        /// Links with votes from users, not just from blocklist seeds
+        pub count_of_rated_links: u32,
+        pub count_of_good_links: u32,
+        pub count_of_bad_links: u32,
+        pub count_of_controversial_links: u32,
+        pub updated_at: String,
+    }
+
+    /// A page of the `Bad` links, written by `compute-filter-lists` so that
+    /// `GET /v1/filter-list` doesn't have to scan. Every page of a list is
+    /// written in the same transaction.
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    pub struct FilterListPage {
+        #[serde(rename = "SK", with = "keys::page")]
+        pub page: u32,
+        pub hostnames: Vec<String>,
+        pub generated_at: String,
+    }
+
+    /// A vote as seen through the `UserVotes` index
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    pub struct UserVote {
+        #[serde(rename = "PK", with = "keys::link")]
+        pub link: Link,
+        pub value: i32,
+        pub created_at: String,
+    }
+
+    /// A link's votes for a single day
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    pub struct LinkHistory {
+        #[serde(rename = "PK", with = "keys::day")]
+        pub day: String,
+        #[serde(rename = "SK", with = "keys::link")]
+        pub link: Link,
+        pub count_of_votes: u32,
+        pub sum_of_votes: i32,
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::ban_rules::{BanAction, BanRule};
+        use aws_sdk_dynamodb::model::AttributeValue::*;
+
+        fn round_trip<T>(entity: &T, entity_type: &str) -> T
+        where
+            T: Serialize + DeserializeOwned,
+        {
+            from_item(&to_item(entity, entity_type).unwrap()).unwrap()
+        }
+
+        #[test]
+        fn test_round_trips() {
+            let vote = Vote {
+                link: Link::new("example.com"),
+                value: -1,
+                user_id: Uuid::from_u128(1),
+                created_at: "2023-02-08T10:00:00Z".to_string(),
+                reason: Some(VoteReason::SeoSpam),
+                dimension: Some(Dimension::AdIntrusiveness),
+                weight: 0.5,
+            };
+            let item = to_item(&vote, "Vote").unwrap();
+            assert_eq!(item["PK"], S("link#example.com".to_string()));
+            assert_eq!(
+                item["SK"],
+                S("user#00000000-0000-0000-0000-000000000001".to_string())
+            );
+            assert_eq!(item["reason"], S("SeoSpam".to_string()));
+            assert_eq!(Vote::try_from(&item).unwrap(), vote);
+
+            let user = User {
+                is_banned: false,
+                is_shadow_banned: true,
+                created_at: "2023-02-08T10:00:00Z".to_string(),
+                reputation: 1.25,
+                count_of_bans: 2,
+                last_vote_day: "2023-02-08".to_string(),
+                count_of_votes_on_last_vote_day: 3,
+                recent_votes_received_at: vec!["2023-02-08T10:00:00Z".to_string()],
+            };
+            assert_eq!(round_trip(&user, "User"), user);
+
+            let settings = Settings {
+                voting_is_disabled: false,
+                maximum_votes_per_user_per_day: 10,
+                freeze_suspicious_links: true,
+                ban_rules: vec![BanRuleConfig {
+                    name: "fast".to_string(),
+                    rule: BanRule::VoteRate {
+                        maximum_votes: 30,
+                        minutes: 1,
+                    },
+                    action: BanAction::ShadowBan,
+                }],
+            };
+            let item = to_item(&settings, "Settings").unwrap();
+            assert!(item["ban_rules"].is_s());
+            assert_eq!(Settings::try_from(&item).unwrap(), settings);
+
+            let link_detail = LinkDetail {
+                link: Link::new("example.com"),
+                count_of_votes: 4,
+                sum_of_votes: -2,
+                weighted_sum_of_votes: -1.5,
+                count_of_reasons: BTreeMap::from([(VoteReason::Malware, 3)]),
+                dimensions: BTreeMap::from([(
+                    Dimension::ContentQuality,
+                    DimensionDetail {
+                        count_of_votes: 2,
+                        sum_of_votes: -2,
+                    },
+                )]),
+                frozen_score: Some(Score::Good),
+                created_at: None,
+                count_of_seed_votes: Some(3),
+            };
+            let item = to_item(&link_detail, "LinkDetail").unwrap();
+            assert_eq!(item["count_of_malware_votes"], N("3".to_string()));
+            assert_eq!(item["sum_of_content_quality_votes"], N("-2".to_string()));
+            assert!(!item.contains_key("created_at"));
+            assert_eq!(LinkDetail::try_from(&item).unwrap(), link_detail);
+
+            let link_history = LinkHistory {
+                day: "2023-02-08".to_string(),
+                link: Link::new("example.com"),
+                count_of_votes: 3,
+                sum_of_votes: 1,
+            };
+            let item = to_item(&link_history, "LinkHistory").unwrap();
+            assert_eq!(item["PK"], S("day#2023-02-08".to_string()));
+            assert_eq!(LinkHistory::try_from(&item).unwrap(), link_history);
+
+            let user_history = UserHistory {
+                day: "2023-02-08".to_string(),
+                count_of_votes: 3,
+                sum_of_votes: 1,
+            };
+            assert_eq!(round_trip(&user_history, "UserHistory"), user_history);
+
+            let link_override = LinkOverride {
+                link: Link::new("example.com"),
+                score: Score::Bad,
+                reason: "Malware".to_string(),
+                expires_at: Some("2030-01-01T00:00:00Z".to_string()),
+            };
+            assert_eq!(round_trip(&link_override, "LinkOverride"), link_override);
+
+            let migration_progress = MigrationProgress {
+                target_version: 1,
+                last_evaluated_key: None,
+                count_of_scanned_items: 100,
+                count_of_migrated_items: 10,
+                started_at: "2023-02-08T10:00:00Z".to_string(),
+                updated_at: "2023-02-08T10:05:00Z".to_string(),
+                completed_at: Some("2023-02-08T10:05:00Z".to_string()),
+            };
+            assert_eq!(
+                round_trip(&migration_progress, "MigrationProgress"),
+                migration_progress
+            );
+
+            let daily_stats = DailyStats {
+                day: "2023-02-08".to_string(),
+                count_of_votes: 120,
+                count_of_active_voters: 14,
+                updated_at: "2023-02-08T10:05:00Z".to_string(),
+            };
+            let item = to_item(&daily_stats, "DailyStats").unwrap();
+            assert_eq!(item["SK"], S("day#2023-02-08".to_string()));
+            assert_eq!(DailyStats::try_from(&item).unwrap(), daily_stats);
+
+            let link_stats = LinkStats {
+                count_of_rated_links: 900,
+                count_of_good_links: 30,
+                count_of_bad_links: 12,
+                count_of_controversial_links: 2,
+                updated_at: "2023-02-08T00:00:00Z".to_string(),
+            };
+            assert_eq!(round_trip(&link_stats, "LinkStats"), link_stats);
+
+            let filter_list_page = FilterListPage {
+                page: 3,
+                hostnames: vec!["bad.com".to_string(), "spam.net".to_string()],
+                generated_at: "2023-02-08T10:05:00Z".to_string(),
+            };
+            let item = to_item(&filter_list_page, "FilterListPage").unwrap();
+            assert_eq!(item["SK"], S("page#0003".to_string()));
+            assert_eq!(FilterListPage::try_from(&item).unwrap(), filter_list_page);
+        }
+
+        #[test]
+        fn test_older_items_are_migrated() {
+            // From before reputations and schema versions
+            let vote = HashMap::from([
+                ("PK".to_string(), S("link#example.com".to_string())),
+                (
+                    "SK".to_string(),
+                    S("shadow#00000000-0000-0000-0000-000000000001".to_string()),
+                ),
+                ("entity_type".to_string(), S("ShadowVote".to_string())),
+                ("value".to_string(), N("1".to_string())),
+                (
+                    "created_at".to_string(),
+                    S("2023-02-08T10:00:00Z".to_string()),
+                ),
+            ]);
+            let vote = Vote::try_from(&vote).unwrap();
+            assert_eq!(vote.weight, 1.0);
+            assert_eq!(vote.user_id, Uuid::from_u128(1));
+
+            let link_detail = HashMap::from([
+                ("PK".to_string(), S("link#example.com".to_string())),
+                ("entity_type".to_string(), S("LinkDetail".to_string())),
+                ("count_of_votes".to_string(), N("3".to_string())),
+                ("sum_of_votes".to_string(), N("-3".to_string())),
+                ("seed_sources".to_string(), Ss(vec!["hosts".to_string()])),
+            ]);
+            let link_detail = LinkDetail::try_from(&link_detail).unwrap();
+            assert_eq!(link_detail.weighted_sum_of_votes, -3.0);
+
+            // But the latest version has to be complete
+            let user = HashMap::from([
+                ("entity_type".to_string(), S("User".to_string())),
+                ("is_banned".to_string(), Bool(false)),
+                (
+                    "created_at".to_string(),
+                    S("2023-02-08T10:00:00Z".to_string()),
+                ),
+                ("schema_version".to_string(), N(SCHEMA_VERSION.to_string())),
+            ]);
+            assert!(User::try_from(&user).is_err());
         }
     }
 }
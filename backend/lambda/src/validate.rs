@@ -1,4 +1,4 @@
-use crate::types::api;
+use crate::types::{api, VoteReason};
 use chrono::DateTime;
 use lambda_http::Error;
 use lambda_http::{aws_lambda_events::query_map::QueryMap, Body};
@@ -10,9 +10,18 @@ pub fn validate_get_scores_request(query_map: QueryMap) -> Result<api::ScoresReq
     let links_query_parameter = query_map
         .first("from")
         .ok_or("Incorrect query parameters. Expected `from`")?;
-    let links = serde_json::from_str::<api::ScoresRequest>(links_query_parameter)?;
-    links.validate()?;
-    Ok(links)
+    validate_scores_request(links_query_parameter.as_bytes())
+}
+
+pub fn validate_post_scores_request(body: &Body) -> Result<api::ScoresRequest, Error> {
+    validate_scores_request(body)
+}
+
+// Both the GET and POST forms of `/v1/scores` carry the same JSON document
+fn validate_scores_request(json: &[u8]) -> Result<api::ScoresRequest, Error> {
+    let scores_request = serde_json::from_slice::<api::ScoresRequest>(json)?;
+    scores_request.validate()?;
+    Ok(scores_request)
 }
 
 pub fn validate_vote_request(body: &Body) -> Result<api::VoteRequest, Error> {
@@ -21,14 +30,19 @@ pub fn validate_vote_request(body: &Body) -> Result<api::VoteRequest, Error> {
     Ok(vote_request)
 }
 
+pub fn validate_votes_request(body: &Body) -> Result<api::VotesRequest, Error> {
+    let votes_request = serde_json::from_slice::<api::VotesRequest>(body)?;
+    votes_request.validate()?;
+    Ok(votes_request)
+}
+
 lazy_static! {
     // For timestamps in the format "2023-02-02T09:36:03Z"
     static ref TIMESTAMP_REGEX: Regex = Regex::new(r"^\d{4}-\d\d-\d\dT\d\d:\d\d:\d\dZ$").unwrap();
 }
 
-pub fn is_timestamp_valid(timestamp: &String) -> Result<(), ValidationError> {
-    if (!TIMESTAMP_REGEX.is_match(&timestamp))
-        || (DateTime::parse_from_rfc3339(&timestamp).is_err())
+pub fn is_timestamp_valid(timestamp: &str) -> Result<(), ValidationError> {
+    if (!TIMESTAMP_REGEX.is_match(timestamp)) || (DateTime::parse_from_rfc3339(timestamp).is_err())
     {
         return Err(ValidationError::new(
             "Timestamp should be in the RFC3339 format 2023-02-02T09:36:03Z",
@@ -44,6 +58,28 @@ pub fn is_vote_value_valid(vote_value: i32) -> Result<(), ValidationError> {
     Ok(())
 }
 
+pub fn is_vote_reason_valid(
+    vote_value: i32,
+    reason: &Option<VoteReason>,
+) -> Result<(), ValidationError> {
+    if reason.is_some() && vote_value != -1 {
+        return Err(ValidationError::new("Only downvotes can have a reason"));
+    }
+    Ok(())
+}
+
+pub fn validate_vote_request_reason(
+    vote_request: &api::VoteRequest,
+) -> Result<(), ValidationError> {
+    is_vote_reason_valid(vote_request.value, &vote_request.reason)
+}
+
+pub fn validate_queued_vote_request_reason(
+    vote_request: &api::QueuedVoteRequest,
+) -> Result<(), ValidationError> {
+    is_vote_reason_valid(vote_request.value, &vote_request.reason)
+}
+
 // Copyright 2018-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MIT
 // https://docs.rs/hostname-validator
@@ -60,9 +96,9 @@ pub fn is_vote_value_valid(vote_value: i32) -> Result<(), ValidationError> {
 /// - Its lables do not start or end with '-' or '.'.
 pub fn is_hostname_valid(hostname: &str) -> Result<(), ValidationError> {
     fn is_valid_char(byte: u8) -> bool {
-        (b'a'..=b'z').contains(&byte)
-            || (b'A'..=b'Z').contains(&byte)
-            || (b'0'..=b'9').contains(&byte)
+        byte.is_ascii_lowercase()
+            || byte.is_ascii_uppercase()
+            || byte.is_ascii_digit()
             || byte == b'-'
             || byte == b'.'
     }
@@ -83,17 +119,18 @@ pub fn is_hostname_valid(hostname: &str) -> Result<(), ValidationError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{api::ScoresRequest, Link};
+    use crate::types::{
+        api::{ScoresRequest, VotesRequest},
+        Link,
+    };
     use std::collections::HashMap;
 
     // Make it easy to create new ScoresRequest objects
     impl ScoresRequest {
         pub fn new(links: Vec<&str>) -> Self {
             ScoresRequest {
-                links: links
-                    .into_iter()
-                    .map(|link| Link::new(link))
-                    .collect::<Vec<Link>>(),
+                links: links.into_iter().map(Link::new).collect::<Vec<Link>>(),
+                dimensions: vec![],
             }
         }
     }
@@ -101,8 +138,8 @@ mod tests {
     #[test]
     fn test_is_timestamp_valid() {
         // Valid timestamps
-        for timestamp in &["VaLiD-HoStNaMe"] {
-            assert_eq!(is_hostname_valid(timestamp), Ok(()));
+        for timestamp in ["2023-02-02T09:36:03Z", "2022-07-27T12:30:00Z"] {
+            assert_eq!(is_timestamp_valid(timestamp), Ok(()));
         }
 
         // Invalid timestamps
@@ -117,7 +154,7 @@ mod tests {
             "2020-12-31 21:07:14-05:00",
         ] {
             assert_eq!(
-                is_timestamp_valid(&invalid_timestamp.to_string()),
+                is_timestamp_valid(invalid_timestamp),
                 Err(ValidationError::new(
                     "Timestamp should be in the RFC3339 format 2023-02-02T09:36:03Z"
                 ))
@@ -133,7 +170,7 @@ mod tests {
         ) -> Result<ScoresRequest, Error> {
             validate_get_scores_request(QueryMap::from(HashMap::from([(
                 query_key.to_string(),
-                serde_json::to_string(query_value.into()).unwrap(),
+                serde_json::to_string(query_value).unwrap(),
             )])))
         }
 
@@ -191,6 +228,97 @@ mod tests {
             .contains("Hostname is invalid"));
     }
 
+    #[test]
+    fn test_validate_post_scores_request() {
+        fn test_helper<T: serde::Serialize>(body: &T) -> Result<ScoresRequest, Error> {
+            validate_post_scores_request(&Body::from(serde_json::to_string(body).unwrap()))
+        }
+
+        // Happy path
+        let value = ScoresRequest::new(vec!["www.google.com", "abc.com", "domain.me"]);
+        let result = test_helper(&value);
+        assert_eq!(result.unwrap(), value);
+
+        // Empty body
+        let result = validate_post_scores_request(&Body::Empty);
+        assert!(result.is_err());
+
+        // Not enough links
+        let result = test_helper(&ScoresRequest::new(vec![]));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Validation error: length"));
+
+        // Too many links
+        let result = test_helper(&ScoresRequest::new(vec!["www.google.com"; 101]));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Validation error: length"));
+
+        // Incorrect value type
+        let result = test_helper(&"www.google.com");
+        assert!(result.unwrap_err().to_string().contains("invalid type"));
+
+        // Invalid hostname
+        let result = test_helper(&ScoresRequest::new(vec!["www.google.com", "abc;;;com"]));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Hostname is invalid"));
+    }
+
+    #[test]
+    fn test_validate_votes_request() {
+        fn queued_vote(hostname: &str, value: i32, created_at: &str) -> serde_json::Value {
+            serde_json::json!({
+                "link": {"hostname": hostname},
+                "value": value,
+                "user_id": "b4a70900-1c86-4dd7-8d38-0dc05bfb1e0a",
+                "created_at": created_at,
+            })
+        }
+        fn test_helper(votes: Vec<serde_json::Value>) -> Result<VotesRequest, Error> {
+            let body = serde_json::json!({ "votes": votes }).to_string();
+            validate_votes_request(&Body::from(body))
+        }
+
+        // Happy path
+        let result = test_helper(vec![
+            queued_vote("abc.com", 1, "2022-07-27T12:30:00Z"),
+            queued_vote("domain.me", -1, "2022-07-26T08:00:00Z"),
+        ]);
+        assert_eq!(result.unwrap().votes.len(), 2);
+
+        // Not enough votes
+        let result = test_helper(vec![]);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Validation error: length"));
+
+        // Too many votes
+        let result = test_helper(vec![queued_vote("abc.com", 1, "2022-07-27T12:30:00Z"); 21]);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Validation error: length"));
+
+        // Invalid votes anywhere in the batch fail the whole request
+        for invalid_vote in [
+            queued_vote("abc;;;com", 1, "2022-07-27T12:30:00Z"),
+            queued_vote("abc.com", 2, "2022-07-27T12:30:00Z"),
+            queued_vote("abc.com", 1, "2022-07-27 12:30:00"),
+        ] {
+            let result = test_helper(vec![
+                queued_vote("abc.com", 1, "2022-07-27T12:30:00Z"),
+                invalid_vote,
+            ]);
+            assert!(result.is_err());
+        }
+    }
+
     #[test]
     fn test_is_vote_value_valid() {
         assert_eq!(is_vote_value_valid(1), Ok(()));
@@ -203,6 +331,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_is_vote_reason_valid() {
+        assert_eq!(is_vote_reason_valid(1, &None), Ok(()));
+        assert_eq!(is_vote_reason_valid(-1, &None), Ok(()));
+        for reason in VoteReason::ALL {
+            assert_eq!(is_vote_reason_valid(-1, &Some(reason)), Ok(()));
+            assert_eq!(
+                is_vote_reason_valid(1, &Some(reason)),
+                Err(ValidationError::new("Only downvotes can have a reason"))
+            );
+        }
+
+        // Unknown reasons are rejected when parsing the request
+        let body = Body::from(
+            r#"{"link": {"hostname": "abc.com"}, "value": -1, "reason": "Boring",
+                "user_id": "b4a70900-1c86-4dd7-8d38-0dc05bfb1e0a"}"#,
+        );
+        assert!(validate_vote_request(&body)
+            .unwrap_err()
+            .to_string()
+            .contains("unknown variant"));
+        let body = Body::from(
+            r#"{"link": {"hostname": "abc.com"}, "value": 1, "reason": "SeoSpam",
+                "user_id": "b4a70900-1c86-4dd7-8d38-0dc05bfb1e0a"}"#,
+        );
+        assert!(validate_vote_request(&body)
+            .unwrap_err()
+            .to_string()
+            .contains("Only downvotes can have a reason"));
+    }
+
     #[test]
     fn test_is_hostname_valid() {
         // Valid hostnames
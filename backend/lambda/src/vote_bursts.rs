@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    dynamodb::*,
+    scoring::overall_score,
+    types::{
+        database::{LinkDetail, LinkHistory, Settings, User},
+        Config, Link,
+    },
+};
+use aws_sdk_dynamodb::{
+    model::{AttributeValue, AttributeValue::*},
+    Client,
+};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use lambda_http::Error;
+use tracing::*;
+
+// How many previous days make up a link's normal level of voting
+const TRAILING_DAYS: i64 = 7;
+// Below this many votes in a day nothing is worth looking at
+const MINIMUM_BURST_VOTES: u32 = 20;
+const BURST_MULTIPLIER: f64 = 5.0;
+// Brigades push in one direction, organic attention is more mixed
+const ONE_SIDED_RATIO: f64 = 0.9;
+const NEW_USER_AGE_IN_DAYS: i64 = 1;
+const NEW_USER_SHARE: f64 = 0.5;
+// Only the busiest links of the day are checked
+const CANDIDATE_LINKS_PER_DAY: i32 = 100;
+
+#[derive(Debug, PartialEq)]
+pub struct VoteBurst {
+    pub link: Link,
+    pub day: String,
+    pub count_of_votes: u32,
+    pub sum_of_votes: i32,
+    pub baseline_count_of_votes: f64,
+    pub share_of_new_users: f64,
+    pub reasons: Vec<String>,
+}
+
+/// Compare a link's votes on one day against the days before it. `trailing_histories`
+/// only has the days that had votes, the rest of the `TRAILING_DAYS` count as zero.
+pub fn detect_vote_burst(
+    day_history: &LinkHistory,
+    trailing_histories: &[LinkHistory],
+    count_of_new_users: u32,
+) -> Option<VoteBurst> {
+    let LinkHistory {
+        count_of_votes,
+        sum_of_votes,
+        ..
+    } = day_history;
+    if *count_of_votes < MINIMUM_BURST_VOTES {
+        return None;
+    }
+
+    let baseline_count_of_votes = trailing_histories
+        .iter()
+        .map(|history| history.count_of_votes as f64)
+        .sum::<f64>()
+        / TRAILING_DAYS as f64;
+    let increase = *count_of_votes as f64 / baseline_count_of_votes.max(1.0);
+    let is_one_sided =
+        sum_of_votes.unsigned_abs() as f64 / *count_of_votes as f64 >= ONE_SIDED_RATIO;
+    let share_of_new_users = count_of_new_users as f64 / *count_of_votes as f64;
+
+    let mut reasons = vec![];
+    if increase >= BURST_MULTIPLIER && is_one_sided {
+        reasons.push(format!(
+            "{} votes is {:.1}x the trailing average, {} in the same direction",
+            count_of_votes,
+            increase,
+            sum_of_votes.unsigned_abs()
+        ));
+    }
+    if share_of_new_users >= NEW_USER_SHARE {
+        reasons.push(format!(
+            "{:.0}% of the votes are from users created in the last {} day(s)",
+            share_of_new_users * 100.0,
+            NEW_USER_AGE_IN_DAYS
+        ));
+    }
+    if reasons.is_empty() {
+        return None;
+    }
+
+    Some(VoteBurst {
+        link: day_history.link.clone(),
+        day: day_history.day.clone(),
+        count_of_votes: *count_of_votes,
+        sum_of_votes: *sum_of_votes,
+        baseline_count_of_votes,
+        share_of_new_users,
+        reasons,
+    })
+}
+
+/// Check the busiest links of `day` for vote bursts and add any new ones to the
+/// review queue. If the settings say so, the links' scores are frozen as well.
+/// Returns the bursts that were queued.
+pub async fn detect_vote_bursts(
+    day: &str,
+    now: DateTime<Utc>,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<VoteBurst>, Error> {
+    let settings = dynamo_db_client
+        .get_item()
+        .table_name(&config.table_name)
+        .set_key(Some(get_settings()))
+        .send()
+        .await?;
+    let settings = Settings::try_from(settings.item().ok_or("No settings")?)?;
+
+    let candidates = dynamo_db_client
+        .query()
+        .table_name(&config.table_name)
+        .index_name("DailyLinkHistoryByCountOfVotes")
+        .key_condition_expression("DailyLinkHistory_PK = :day AND count_of_votes >= :minimum")
+        .expression_attribute_values(":day", S(format!("day#{}", day)))
+        .expression_attribute_values(":minimum", N(MINIMUM_BURST_VOTES.to_string()))
+        .scan_index_forward(false)
+        .limit(CANDIDATE_LINKS_PER_DAY)
+        .send()
+        .await?;
+
+    let mut vote_bursts = vec![];
+    for item in candidates.items().unwrap_or_default() {
+        let day_history = LinkHistory::try_from(item)?;
+
+        let trailing_histories =
+            get_trailing_histories(&day_history, config, dynamo_db_client).await?;
+        let count_of_new_users = count_new_users(&day_history, config, dynamo_db_client).await?;
+        if let Some(vote_burst) =
+            detect_vote_burst(&day_history, &trailing_histories, count_of_new_users)
+        {
+            vote_bursts.push(vote_burst);
+        }
+    }
+
+    // Links can be flagged again on each run, only queue them the first time
+    let keys = vote_bursts
+        .iter()
+        .map(|vote_burst| get_link_review(&vote_burst.link, &vote_burst.day))
+        .collect();
+    let already_queued: HashSet<String> = batch_get_items(keys, config, dynamo_db_client)
+        .await?
+        .iter()
+        .filter_map(|item| {
+            item.get("SK")
+                .and_then(|sort_key| sort_key.as_s().ok())
+                .cloned()
+        })
+        .collect();
+    vote_bursts.retain(|vote_burst| {
+        !already_queued.contains(&format!(
+            "link#{}#{}",
+            vote_burst.link.hostname, vote_burst.day
+        ))
+    });
+
+    let link_details: HashMap<Link, LinkDetail> = batch_get_items(
+        vote_bursts
+            .iter()
+            .map(|vote_burst| get_link_detail(&vote_burst.link))
+            .collect(),
+        config,
+        dynamo_db_client,
+    )
+    .await?
+    .iter()
+    .map(|item| LinkDetail::try_from(item).map(|detail| (detail.link.clone(), detail)))
+    .collect::<Result<_, _>>()?;
+
+    let created_at = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    for vote_burst in &vote_bursts {
+        warn!("Vote burst detected [vote_burst={:?}]", vote_burst);
+        let mut write_requests = vec![put_link_review(
+            vote_burst,
+            settings.freeze_suspicious_links,
+            &created_at,
+            config,
+        )];
+        if settings.freeze_suspicious_links {
+            if let Some(link_detail) = link_details.get(&vote_burst.link) {
+                let score = overall_score(link_detail, config.use_weighted_scores);
+                write_requests.push(freeze_link_score(&vote_burst.link, &score, config));
+            }
+        }
+        dynamo_db_client
+            .transact_write_items()
+            .set_transact_items(Some(write_requests))
+            .send()
+            .await?;
+    }
+
+    Ok(vote_bursts)
+}
+
+/// Take a link's review for `day` off the queue. The link's frozen score is
+/// removed too, unless another of its reviews still has it frozen. Returns whether
+/// the link was unfrozen.
+pub async fn resolve_link_review(
+    link: &Link,
+    day: &str,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<bool, Error> {
+    let review = dynamo_db_client
+        .get_item()
+        .table_name(&config.table_name)
+        .set_key(Some(get_link_review(link, day)))
+        .send()
+        .await?;
+    let review = review.item().ok_or("No such review")?;
+    let is_frozen = *review
+        .get("is_frozen")
+        .ok_or("No is_frozen")?
+        .as_bool()
+        .or(Err("is_frozen is not a bool"))?;
+
+    let mut write_requests = vec![delete_link_review(link, day, config)];
+    let is_unfrozen =
+        is_frozen && !has_other_frozen_reviews(link, day, config, dynamo_db_client).await?;
+    if is_unfrozen {
+        write_requests.push(unfreeze_link_score(link, config));
+    }
+    dynamo_db_client
+        .transact_write_items()
+        .set_transact_items(Some(write_requests))
+        .send()
+        .await?;
+    Ok(is_unfrozen)
+}
+
+async fn has_other_frozen_reviews(
+    link: &Link,
+    day: &str,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<bool, Error> {
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .query()
+            .table_name(&config.table_name)
+            .key_condition_expression("PK = :review AND begins_with(SK, :link)")
+            .filter_expression("is_frozen = :true AND #day <> :day")
+            // `day` is a reserved word
+            .expression_attribute_names("#day", "day")
+            .expression_attribute_values(":review", S("review".to_string()))
+            .expression_attribute_values(":link", S(format!("link#{}#", link.hostname)))
+            .expression_attribute_values(":true", Bool(true))
+            .expression_attribute_values(":day", S(day.to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        if response.count() > 0 {
+            return Ok(true);
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            return Ok(false);
+        }
+    }
+}
+
+async fn get_trailing_histories(
+    day_history: &LinkHistory,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<Vec<LinkHistory>, Error> {
+    let day = NaiveDate::parse_from_str(&day_history.day, "%Y-%m-%d")?;
+    let keys = (1..=TRAILING_DAYS)
+        .map(|days_before| {
+            let trailing_day = (day - Duration::days(days_before)).format("%Y-%m-%d");
+            HashMap::from([
+                ("PK".to_string(), S(format!("day#{}", trailing_day))),
+                (
+                    "SK".to_string(),
+                    S(format!("link#{}", day_history.link.hostname)),
+                ),
+            ])
+        })
+        .collect();
+    batch_get_items(keys, config, dynamo_db_client)
+        .await?
+        .iter()
+        .map(LinkHistory::try_from)
+        .collect()
+}
+
+// How many of the day's voters on the link were created shortly before voting
+async fn count_new_users(
+    day_history: &LinkHistory,
+    config: &Config,
+    dynamo_db_client: &Client,
+) -> Result<u32, Error> {
+    let mut user_keys = vec![];
+    let mut exclusive_start_key: Option<HashMap<String, AttributeValue>> = None;
+    loop {
+        let response = dynamo_db_client
+            .query()
+            .table_name(&config.table_name)
+            .key_condition_expression("PK = :link AND begins_with(SK, :user)")
+            .filter_expression("begins_with(created_at, :day)")
+            .expression_attribute_values(":link", S(format!("link#{}", day_history.link.hostname)))
+            .expression_attribute_values(":user", S("user#".to_string()))
+            .expression_attribute_values(":day", S(day_history.day.clone()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+        for item in response.items().unwrap_or_default() {
+            let sort_key = item.get("SK").ok_or("No SK")?.clone();
+            user_keys.push(HashMap::from([
+                ("PK".to_string(), sort_key.clone()),
+                ("SK".to_string(), sort_key),
+            ]));
+        }
+        exclusive_start_key = response.last_evaluated_key().cloned();
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+
+    let day = NaiveDate::parse_from_str(&day_history.day, "%Y-%m-%d")?;
+    let new_user_cutoff = (day - Duration::days(NEW_USER_AGE_IN_DAYS))
+        .format("%Y-%m-%d")
+        .to_string();
+    let mut count_of_new_users = 0;
+    for item in batch_get_items(user_keys, config, dynamo_db_client).await? {
+        let user = User::try_from(&item)?;
+        if user.created_at[..10] >= *new_user_cutoff {
+            count_of_new_users += 1;
+        }
+    }
+    Ok(count_of_new_users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_history(day: &str, count_of_votes: u32, sum_of_votes: i32) -> LinkHistory {
+        LinkHistory {
+            day: day.to_string(),
+            link: Link::new("brigaded.com"),
+            count_of_votes,
+            sum_of_votes,
+        }
+    }
+
+    #[test]
+    fn test_detect_vote_burst() {
+        let trailing_histories = vec![
+            link_history("2023-02-01", 3, 1),
+            link_history("2023-02-03", 5, -1),
+            link_history("2023-02-05", 6, 2),
+        ];
+
+        // Too few votes to matter, however sudden
+        let day_history = link_history("2023-02-08", 19, -19);
+        assert_eq!(detect_vote_burst(&day_history, &[], 19), None);
+
+        // Busy, but in line with the trailing average
+        let day_history = link_history("2023-02-08", 20, -20);
+        let busy_trailing_histories = vec![link_history("2023-02-07", 30, 5); 7];
+        assert_eq!(
+            detect_vote_burst(&day_history, &busy_trailing_histories, 0),
+            None
+        );
+
+        // A big jump, but with votes going both ways
+        let day_history = link_history("2023-02-08", 40, 10);
+        assert_eq!(
+            detect_vote_burst(&day_history, &trailing_histories, 0),
+            None
+        );
+
+        // A big jump, all in one direction
+        let day_history = link_history("2023-02-08", 40, -38);
+        let vote_burst = detect_vote_burst(&day_history, &trailing_histories, 0).unwrap();
+        assert_eq!(vote_burst.baseline_count_of_votes, 2.0);
+        assert_eq!(
+            vote_burst.reasons,
+            vec!["40 votes is 20.0x the trailing average, 38 in the same direction"]
+        );
+
+        // Mostly new users, even without a jump
+        let day_history = link_history("2023-02-08", 30, 10);
+        let vote_burst = detect_vote_burst(&day_history, &busy_trailing_histories, 15).unwrap();
+        assert_eq!(vote_burst.share_of_new_users, 0.5);
+        assert_eq!(
+            vote_burst.reasons,
+            vec!["50% of the votes are from users created in the last 1 day(s)"]
+        );
+
+        // Both at once
+        let day_history = link_history("2023-02-08", 100, 100);
+        let vote_burst = detect_vote_burst(&day_history, &trailing_histories, 90).unwrap();
+        assert_eq!(vote_burst.reasons.len(), 2);
+    }
+}